@@ -1,6 +1,12 @@
 use std::collections::HashMap;
 
-use netfn_transport_http::HttpTransport;
+use futures::StreamExt as _;
+use netfn::{DecodeResponse as _, reflection::ReflectionClient};
+use netfn_transport_http::{
+    ContentEncoding, HttpTransport, Routing, StreamFormat,
+    reqwest::header::{HeaderName, HeaderValue},
+};
+use serde::{Deserialize, Serialize};
 
 pub fn main() {
     #[cfg(not(target_arch = "wasm32"))]
@@ -25,8 +31,30 @@ pub async fn example() {
         tokio::time::sleep(std::time::Duration::from_secs(1)).await;
     }
 
-    let transport: HttpTransport = "http://localhost:3210/".try_into().unwrap();
-    let client = TestApiClient::new(transport);
+    let transport = HttpTransport::builder()
+        .user_agent(HeaderValue::from_static("netfn-example"))
+        .build("http://localhost:3210/")
+        .unwrap();
+    let client = TestApiClient::new(transport.clone());
+    let url = "http://localhost:3210/";
+    let get_client = TestApiClient::new(
+        HttpTransport::builder()
+            .get_read_only(true)
+            .build(url)
+            .unwrap(),
+    );
+    let path_client = TestApiClient::new(
+        HttpTransport::builder()
+            .routing(Routing::Path)
+            .build(url)
+            .unwrap(),
+    );
+    let gzip_client = TestApiClient::new(
+        HttpTransport::builder()
+            .compress_requests(ContentEncoding::Gzip, 64)
+            .build(url)
+            .unwrap(),
+    );
 
     let map = [("hello", "world"), ("bye", "world")]
         .into_iter()
@@ -49,6 +77,23 @@ pub async fn example() {
     println!("{:#?}", client.qaz("hello world".to_owned()).await);
     println!("<<<<\n");
 
+    println!(">>>> qaz (GET)");
+    println!("{:#?}", get_client.qaz("hello get".to_owned()).await);
+    println!("<<<<\n");
+
+    println!(">>>> qaz (path)");
+    println!("{:#?}", path_client.qaz("hello path".to_owned()).await);
+    println!("<<<<\n");
+
+    println!(">>>> qaz (gzip)");
+    println!("{:#?}", gzip_client.qaz("hello gzip ".repeat(16)).await);
+    println!("<<<<\n");
+
+    println!(">>>> foo (missing endpoint)");
+    let missing: HttpTransport = "http://localhost:3210/missing/".try_into().unwrap();
+    println!("{:#?}", TestApiClient::new(missing).foo().await);
+    println!("<<<<\n");
+
     println!(">>>> qoz");
     println!("{:#?}", client.qoz(map, 9).await);
     println!("<<<<\n");
@@ -57,44 +102,204 @@ pub async fn example() {
     println!("{:#?}", client.qoz(HashMap::default(), 10).await);
     println!("<<<<\n");
 
+    println!(">>>> reset");
+    println!("{:#?}", client.reset().await);
+    let admin_client = TestApiClient::new(
+        HttpTransport::builder()
+            .header(
+                HeaderName::from_static("x-role"),
+                HeaderValue::from_static("admin"),
+            )
+            .build(url)
+            .unwrap(),
+    );
+    println!("{:#?}", admin_client.reset().await);
+    println!("<<<<\n");
+
+    println!(">>>> qaz (v2)");
+    let v2_client = TestApiV2Client::new(transport.clone());
+    println!("{:#?}", v2_client.qaz("hello v2".to_owned(), 3).await);
+    println!("<<<<\n");
+
+    println!(">>>> count (stream)");
+    let mut count = transport
+        .stream::<_, u32>(
+            COUNT_SERVICE,
+            CountRequest::Count { to: 3 },
+            StreamFormat::EventStream,
+        )
+        .await
+        .unwrap();
+    while let Some(item) = count.next().await {
+        println!("{item:#?}");
+    }
+    println!("<<<<\n");
+
+    println!(">>>> batch");
+    let mut batch = transport.batch();
+    let baz_call = batch
+        .call::<_, u32>(
+            test_api::SERVICE_NAME,
+            test_api::TestApiRequest::Baz(test_api::TestApiBazArgs {}),
+        )
+        .unwrap();
+    let qaz_call = batch
+        .call::<_, Vec<String>>(
+            test_api::SERVICE_NAME,
+            test_api::TestApiRequest::Qaz(test_api::TestApiQazArgs {
+                a0: "hello batch".to_owned(),
+            }),
+        )
+        .unwrap();
+    let results = batch.send().await.unwrap();
+    println!("{:#?}", results.get(baz_call));
+    println!("{:#?}", results.get(qaz_call));
+    println!("<<<<\n");
+
+    println!(">>>> reflection");
+    let reflection = ReflectionClient::new(transport.clone());
+    println!("{:#?}", reflection.list_services().await);
+    println!(
+        "{:#?}",
+        reflection
+            .describe_service(test_api::SERVICE_NAME)
+            .await
+            .map(|service| service
+                .fns
+                .iter()
+                .map(|tfn| tfn.name.clone())
+                .collect::<Vec<_>>())
+    );
+    println!("<<<<\n");
+
     println!(
         "{}",
         serde_json::to_string_pretty(&test_api::TestApiRequest::Foo(test_api::TestApiFooArgs {}))
             .unwrap()
     );
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        use netfn_transport_replay::{RecordTransport, ReplayTransport};
+
+        println!(">>>> qaz (record and replay)");
+        let path = std::env::temp_dir().join("netfn-example.jsonl");
+        let recorder =
+            TestApiClient::new(RecordTransport::create(transport.clone(), &path).unwrap());
+        println!("{:#?}", recorder.qaz("hello record".to_owned()).await);
+        println!("{:#?}", recorder.qoz(HashMap::default(), 10).await);
+        // Handler errors are replayed with their code, so they can be matched like live ones
+        println!("{:#?}", recorder.reset().await);
+
+        let replayer = TestApiClient::new(ReplayTransport::open(&path).unwrap());
+        println!("{:#?}", replayer.qaz("hello record".to_owned()).await);
+        println!("{:#?}", replayer.qoz(HashMap::default(), 10).await);
+        println!("{:#?}", replayer.reset().await);
+        println!("{:#?}", replayer.qaz("not recorded".to_owned()).await);
+        println!("<<<<\n");
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        println!(">>>> qaz (mock)");
+        let mut mock = MockTestApi::new();
+        mock.expect_qaz()
+            .with(|(inp,)| inp == "hello mock")
+            .returning(|(inp,)| vec![inp])
+            .times(1);
+        mock.expect_baz().return_const(7);
+
+        // The dispatcher acts as an in-process transport, so the client is used as it would be
+        // over a network
+        let dispatcher = netfn::server::Dispatcher::new().with_service(mock.into_service());
+        let mock_client = TestApiClient::new(dispatcher);
+        println!("{:#?}", mock_client.qaz("hello mock".to_owned()).await);
+        println!("{:#?}", mock_client.baz().await);
+        println!("<<<<\n");
+    }
+
+    println!(
+        "{:?}",
+        test_api::TestApiRequest::Bar(test_api::TestApiBarArgs { a0: true })
+            .decode_response(serde_json::Value::Null)
+    );
+
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&test_api::DESCRIPTOR).unwrap()
+    );
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&test_api::schema()).unwrap()
+    );
 }
 
 #[cfg(not(target_arch = "wasm32"))]
 async fn serve() {
     use axum::{
         Json, Router,
+        extract::Request,
         http::StatusCode,
-        routing::{any, post},
+        middleware,
+        routing::{any, get},
+    };
+    use netfn::{
+        limit::RateLimit,
+        server::{CallContext, Dispatcher},
     };
-    use netfn::Service as _;
+    use netfn_transport_http::{openapi::OpenApi, server::HttpServer};
     use serde_json::json;
 
-    // build our application with a single route
-    let app =
+    #[derive(Clone)]
+    struct Role(String);
+
+    /// Stands in for real authentication by trusting the role sent by the client.
+    async fn authenticate(mut request: Request) -> Request {
+        let role = request
+            .headers()
+            .get("x-role")
+            .and_then(|role| role.to_str().ok())
+            .map(|role| Role(role.to_owned()));
+        if let Some(role) = role {
+            request.extensions_mut().insert(role);
+        }
+        request
+    }
+
+    let dispatcher = Dispatcher::new()
+        // `into_service` is ambiguous when a type implements several versions of a service, so
+        // the containers are used directly
+        .with_service(test_api::TestApiContainer(TestService))
+        .with_service(test_api_v2::TestApiV2Container(TestService))
+        .with_stream_service(CountService)
+        .with_authorizer(|context: &CallContext<'_>, requires: &str| {
+            context
+                .extensions
+                .get::<Role>()
+                .is_some_and(|role| role.0 == requires)
+        })
+        .with_max_concurrency(64)
+        .with_max_in_flight_per_connection(16)
+        .with_rate_limit(test_api::SERVICE_NAME, RateLimit::per_second(100))
+        .with_reflection();
+
+    let openapi = OpenApi::new("netfn example", "0.1.0")
+        .server("http://localhost:3210/")
+        .with_service::<test_api::TestApiRequest>()
+        .document();
+
+    // build our application with the netfn router at the root, with a limit for each connection
+    let app = HttpServer::new(dispatcher).into_make_service_with(|router| {
         Router::new()
-            .route(
-                "/",
-                post(
-                    |Json(req): Json<
-                        netfn::CallResponseRequest<'static, test_api::TestApiRequest>,
-                    >| async {
-                        let service = TestService.into_service();
-                        println!("{:#?}", req);
-                        Json(service.call(req.call).await)
-                    },
-                ),
-            )
+            .merge(router.layer(middleware::map_request(authenticate)))
+            .route("/openapi.json", get(|| async { Json(openapi) }))
             .fallback(any(|| async {
                 (
                     StatusCode::NOT_FOUND,
                     Json(json!({ "message": "Not Found" })),
                 )
-            }));
+            }))
+    });
 
     // run our app with hyper, listening globally on port 3000
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3210").await.unwrap();
@@ -102,7 +307,7 @@ async fn serve() {
     axum::serve(listener, app).await.unwrap();
 }
 
-#[netfn::service]
+#[netfn::service(schema, mock)]
 trait TestApi {
     /// Foo documentation
     ///
@@ -113,11 +318,17 @@ trait TestApi {
     #[allow(clippy::unused_unit)]
     async fn bar(&self, inp: bool) -> ();
 
+    #[netfn(read_only)]
     async fn baz(&self) -> u32;
 
+    #[netfn(read_only)]
     async fn qaz(&self, inp: String) -> Vec<String>;
 
     async fn qoz(&self, inp: HashMap<String, String>, val: i16) -> Result<bool, String>;
+
+    /// Only callable by admins
+    #[netfn(requires = "admin")]
+    async fn reset(&self);
 }
 
 struct TestService;
@@ -154,4 +365,120 @@ impl TestApi for TestService {
             Ok(true)
         }
     }
+
+    async fn reset(&self) {
+        println!("[reset]");
+    }
+}
+
+/// The second version of `TestApi`, which is served alongside the first.
+#[netfn::service(name = "TestApi", version = 2)]
+trait TestApiV2 {
+    #[netfn(read_only)]
+    async fn qaz(&self, inp: String, count: usize) -> Vec<String>;
+}
+
+impl TestApiV2 for TestService {
+    async fn qaz(&self, inp: String, count: usize) -> Vec<String> {
+        println!("[qaz v2] inp: {inp}, count: {count}");
+        vec![inp; count]
+    }
+}
+
+const COUNT_SERVICE: &str = "Count";
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "fn", content = "args")]
+enum CountRequest {
+    Count {
+        #[serde(rename = "0")]
+        to: u32,
+    },
+}
+
+impl netfn::ServiceRequest for CountRequest {
+    fn fn_name(&self) -> &'static str {
+        "Count"
+    }
+
+    fn read_only(&self) -> bool {
+        true
+    }
+}
+
+struct CountService;
+
+#[cfg(not(target_arch = "wasm32"))]
+impl netfn::server::StreamService for CountService {
+    const NAME: &'static str = COUNT_SERVICE;
+    const FNS: &'static [&'static str] = &["Count"];
+    type Request = CountRequest;
+    type Item = u32;
+
+    fn open(
+        &self,
+        request: CountRequest,
+    ) -> impl futures::Stream<Item = Result<u32, netfn::GenericError<'static>>> + Send + 'static
+    {
+        let CountRequest::Count { to } = request;
+        futures::stream::iter(0..to).map(Ok)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use netfn::{Service as _, server::Dispatcher};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn mock_is_a_service() {
+        let mut mock = MockTestApi::new();
+        mock.expect_qaz()
+            .with(|(inp,)| inp == "first")
+            .return_const(vec![])
+            .times(1);
+        mock.expect_qaz().returning(|(inp,)| vec![inp]);
+
+        let service = mock.into_service();
+        let request = |inp: &str| {
+            test_api::TestApiRequest::Qaz(test_api::TestApiQazArgs { a0: inp.to_owned() })
+        };
+        assert!(matches!(
+            service.call(request("first")).await,
+            test_api::TestApiResponse::Qaz(out) if out.is_empty()
+        ));
+        assert!(matches!(
+            service.call(request("first")).await,
+            test_api::TestApiResponse::Qaz(out) if out == ["first"]
+        ));
+        service.0.checkpoint();
+    }
+
+    #[tokio::test]
+    async fn mock_is_served_by_a_dispatcher() {
+        let mut mock = MockTestApi::new();
+        mock.expect_qoz()
+            .with(|(_, val)| *val == 10)
+            .return_const(Err("10 is not allowed".to_owned()));
+        mock.expect_qoz().return_const(Ok(true)).times(1);
+
+        let client = TestApiClient::new(Dispatcher::new().with_service(mock.into_service()));
+        assert_eq!(
+            client.qoz(HashMap::new(), 10).await.unwrap(),
+            Err("10 is not allowed".to_owned())
+        );
+        assert_eq!(client.qoz(HashMap::new(), 1).await.unwrap(), Ok(true));
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "expects it never to be")]
+    async fn mock_panics_on_calls_it_should_never_get() {
+        let mut mock = MockTestApi::new();
+        mock.expect_baz().never();
+        mock.expect_baz().return_const(1);
+
+        let client = TestApiClient::new(Dispatcher::new().with_service(mock.into_service()));
+        let _ = client.baz().await;
+    }
 }
@@ -0,0 +1,186 @@
+use futures::{SinkExt as _, Stream, StreamExt as _, future};
+use netfn_core::{GenericError, ServiceRequest, Transport};
+use netfn_transport_http::{HttpTransport, StreamFormat};
+use netfn_transport_ws::{WebSocketCodec, WebSocketMessage, WebSocketTransport};
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::{Map, Value};
+use thiserror::Error;
+use tokio_tungstenite::tungstenite::{self, Message};
+
+/// A call to any fn, built from the command line.
+#[derive(Debug, Clone)]
+pub struct DynCall {
+    pub fn_name: &'static str,
+    pub args: Map<String, Value>,
+}
+
+impl Serialize for DynCall {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::SerializeMap as _;
+
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("fn", self.fn_name)?;
+        map.serialize_entry("args", &self.args)?;
+        map.end()
+    }
+}
+
+impl ServiceRequest for DynCall {
+    fn fn_name(&self) -> &'static str {
+        self.fn_name
+    }
+
+    fn read_only(&self) -> bool {
+        false
+    }
+}
+
+pub enum Client {
+    Http(HttpTransport),
+    WebSocket(WebSocketTransport<JsonCodec, tungstenite::Error>),
+}
+
+impl Client {
+    pub async fn connect(url: &str) -> Result<Self, CallError> {
+        if url.starts_with("ws://") || url.starts_with("wss://") {
+            return Self::connect_ws(url).await;
+        }
+
+        // The transport needs the endpoint to be a directory so that path routing can extend it.
+        let url = if url.ends_with('/') {
+            url.to_owned()
+        } else {
+            format!("{url}/")
+        };
+        let transport = HttpTransport::builder()
+            .build(url.as_str())
+            .map_err(|err| CallError::Connect(err.to_string()))?;
+        Ok(Self::Http(transport))
+    }
+
+    async fn connect_ws(url: &str) -> Result<Self, CallError> {
+        let (socket, _) = tokio_tungstenite::connect_async(url)
+            .await
+            .map_err(|err| CallError::Connect(err.to_string()))?;
+        let (sink, stream) = socket.split();
+
+        let mut sink = sink.with(|message| {
+            future::ready(Ok::<_, tungstenite::Error>(match message {
+                WebSocketMessage::Json(json) => Message::text(json),
+                WebSocketMessage::MessagePack(bytes) => Message::binary(bytes),
+            }))
+        });
+        let mut stream = stream.filter_map(|message| {
+            future::ready(match message {
+                Ok(Message::Text(text)) => Some(WebSocketMessage::Json(text.as_str().to_owned())),
+                Ok(Message::Binary(bytes)) => Some(WebSocketMessage::MessagePack(bytes.into())),
+                _ => None,
+            })
+        });
+
+        let (transport, mut listener) = WebSocketTransport::new(JsonCodec, 16);
+        tokio::spawn(async move { listener.listen(&mut sink, &mut stream).await });
+
+        Ok(Self::WebSocket(transport))
+    }
+
+    /// Opens a stream, which is only supported over HTTP for now.
+    pub async fn stream(
+        &self,
+        service: &'static str,
+        call: DynCall,
+    ) -> Result<impl Stream<Item = Result<Value, CallError>> + Unpin, CallError> {
+        let Self::Http(transport) = self else {
+            return Err(CallError::Unsupported("streams over WebSockets"));
+        };
+
+        let stream = transport
+            .stream::<_, Value>(service, call, StreamFormat::EventStream)
+            .await
+            .map_err(CallError::from_http)?;
+        Ok(stream.map(|item| item.map_err(CallError::from_http)))
+    }
+}
+
+// This is implemented for references so that the same connection can be used for reflection.
+impl Transport for &Client {
+    type Error = CallError;
+
+    async fn call<Req, Res>(&self, service: &'static str, request: Req) -> Result<Res, Self::Error>
+    where
+        Req: netfn_core::compat::NetfnSend + Serialize + ServiceRequest,
+        Res: netfn_core::compat::NetfnSend + DeserializeOwned,
+    {
+        match *self {
+            Client::Http(transport) => transport
+                .call(service, request)
+                .await
+                .map_err(CallError::from_http),
+            Client::WebSocket(transport) => {
+                transport
+                    .call(service, request)
+                    .await
+                    .map_err(|err| match err {
+                        netfn_transport_ws::TransportError::Handler(err) => CallError::Handler(err),
+                        err => CallError::Transport(err.to_string()),
+                    })
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct JsonCodec;
+
+impl WebSocketCodec for JsonCodec {
+    type EncodeError = serde_json::Error;
+    type DecodeError = serde_json::Error;
+
+    fn encode<T>(&self, value: &T) -> Result<WebSocketMessage, Self::EncodeError>
+    where
+        T: Serialize,
+    {
+        serde_json::to_string(value).map(WebSocketMessage::Json)
+    }
+
+    fn decode<T>(&self, message: &WebSocketMessage) -> Result<T, Self::DecodeError>
+    where
+        T: DeserializeOwned,
+    {
+        match message {
+            WebSocketMessage::Json(json) => serde_json::from_str(json),
+            WebSocketMessage::MessagePack(_) => Err(serde::de::Error::custom(
+                "MessagePack messages are not supported",
+            )),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum CallError {
+    #[error("failed to connect: {0}")]
+    Connect(String),
+    #[error("{0}")]
+    Handler(GenericError<'static>),
+    #[error("{0}")]
+    Transport(String),
+    #[error("failed to decode response: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("{0} are not supported")]
+    Unsupported(&'static str),
+}
+
+impl CallError {
+    fn from_http(err: netfn_transport_http::TransportError) -> Self {
+        match err {
+            netfn_transport_http::TransportError::Handler(err) => Self::Handler(err),
+            netfn_transport_http::TransportError::Status { status, body } if !body.is_empty() => {
+                Self::Transport(format!("request failed with status {status}: {body}"))
+            }
+            err => Self::Transport(err.to_string()),
+        }
+    }
+}
@@ -0,0 +1,370 @@
+#![warn(clippy::pedantic)]
+
+mod client;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use clap::{Args, Parser, Subcommand};
+use futures::StreamExt as _;
+use netfn_core::{ArgDescriptor, ServiceDescriptor, Transport as _, reflection::ReflectionClient};
+use serde_json::Value;
+
+use crate::client::{CallError, Client, DynCall};
+
+/// Calls netfn services over HTTP or websockets.
+#[derive(Debug, Parser)]
+#[command(name = "netfn", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    #[command(flatten)]
+    Remote(RemoteCommand),
+    /// Compares two files of service descriptors, failing if the new one breaks old clients.
+    ///
+    /// Services are matched by name, unless both files only hold a single service.
+    Check { old: PathBuf, new: PathBuf },
+}
+
+/// Commands that are run against a server.
+#[derive(Debug, Subcommand)]
+enum RemoteCommand {
+    /// Lists the services provided by the server.
+    List(Remote),
+    /// Shows the fns of a service.
+    Describe {
+        #[command(flatten)]
+        remote: Remote,
+        service: String,
+        /// Prints the descriptor as JSON, which can be saved to be checked later.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Calls a fn and prints the response.
+    Call(CallArgs),
+    /// Opens a stream and prints each message as it arrives.
+    Stream(CallArgs),
+}
+
+#[derive(Debug, Args)]
+struct Remote {
+    /// The endpoint of the server, such as `http://localhost:3000/api/` or `ws://localhost:3000`.
+    url: String,
+    /// Reads service descriptors from a JSON file instead of using reflection.
+    #[arg(long)]
+    schema: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+struct CallArgs {
+    #[command(flatten)]
+    remote: Remote,
+    service: String,
+    #[arg(value_name = "FN")]
+    fn_name: String,
+    /// Values for each arg in order.
+    ///
+    /// Args with a string type are sent as they are written. Others are read as JSON if possible
+    /// and as strings otherwise, which is also done for every arg if the service can't be
+    /// described.
+    args: Vec<String>,
+    /// The whole args object as JSON, such as `{"0": "hello"}`.
+    #[arg(long, conflicts_with = "args")]
+    json: Option<String>,
+    /// Sets an arg by name, which needs the service to be described.
+    #[arg(long = "arg", value_name = "NAME=VALUE")]
+    named: Vec<String>,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    match run(cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(Error::Call(CallError::Handler(err))) => {
+            eprintln!("error [{}]: {}", err.code, err.message);
+            ExitCode::FAILURE
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<(), Error> {
+    match cli.command {
+        Command::Remote(command) => run_remote(command).await,
+        Command::Check { old, new } => check(&old, &new),
+    }
+}
+
+async fn run_remote(command: RemoteCommand) -> Result<(), Error> {
+    let remote = match &command {
+        RemoteCommand::List(remote) | RemoteCommand::Describe { remote, .. } => remote,
+        RemoteCommand::Call(args) | RemoteCommand::Stream(args) => &args.remote,
+    };
+    let client = Client::connect(&remote.url).await?;
+    let services = Services {
+        schema: remote.schema.as_deref().map(read_schema).transpose()?,
+        reflection: ReflectionClient::new(&client),
+    };
+
+    match command {
+        RemoteCommand::List(_) => {
+            for name in services.list().await? {
+                println!("{name}");
+            }
+        }
+        RemoteCommand::Describe { service, json, .. } => {
+            let service = services.describe(&service).await?;
+            if json {
+                print_json(&serde_json::to_value(service)?);
+            } else {
+                print_service(&service);
+            }
+        }
+        RemoteCommand::Call(args) => {
+            let (service, call) = args.into_call(&services).await?;
+            let response: Value = (&client).call(service, call).await?;
+            print_json(&response);
+        }
+        RemoteCommand::Stream(args) => {
+            let (service, call) = args.into_call(&services).await?;
+            let mut stream = client.stream(service, call).await?;
+            while let Some(item) = stream.next().await {
+                print_json(&item?);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn check(old: &Path, new: &Path) -> Result<(), Error> {
+    let old = read_schema(old)?;
+    let new = read_schema(new)?;
+
+    let pairs: Vec<_> = match (&*old, &*new) {
+        ([old], [new]) => vec![(old, Some(new))],
+        _ => old
+            .iter()
+            .map(|old| (old, new.iter().find(|new| new.name == old.name)))
+            .collect(),
+    };
+
+    let mut breaking = 0;
+    for (old, new) in pairs {
+        let Some(new) = new else {
+            println!("service {} was removed", old.name);
+            breaking += 1;
+            continue;
+        };
+
+        for change in old.breaking_changes(new) {
+            println!("{}: {change}", old.name);
+            breaking += 1;
+        }
+    }
+
+    if breaking == 0 {
+        println!("no breaking changes");
+        Ok(())
+    } else {
+        Err(Error::Other(format!("found {breaking} breaking change(s)")))
+    }
+}
+
+struct Services<'a> {
+    schema: Option<Vec<ServiceDescriptor<'static>>>,
+    reflection: ReflectionClient<&'a Client>,
+}
+
+impl Services<'_> {
+    async fn list(&self) -> Result<Vec<String>, Error> {
+        if let Some(schema) = &self.schema {
+            return Ok(schema
+                .iter()
+                .map(|service| service.name.to_string())
+                .collect());
+        }
+
+        let list = self.reflection.list_services().await?;
+        Ok(list
+            .services
+            .into_iter()
+            .chain(
+                list.stream_services
+                    .into_iter()
+                    .map(|name| format!("{name} (stream)")),
+            )
+            .collect())
+    }
+
+    async fn describe(&self, service: &str) -> Result<ServiceDescriptor<'static>, Error> {
+        match &self.schema {
+            Some(schema) => schema
+                .iter()
+                .find(|desc| desc.name == service)
+                .cloned()
+                .ok_or_else(|| Error::Other(format!("service {service} is not in the schema"))),
+            None => Ok(self.reflection.describe_service(service).await?),
+        }
+    }
+}
+
+impl CallArgs {
+    async fn into_call(self, services: &Services<'_>) -> Result<(&'static str, DynCall), Error> {
+        // Positional args can still be guessed if the fn can't be described, such as when it is on
+        // a stream service, but named args need to know the order
+        let service = match (&self.json, self.named.is_empty()) {
+            (_, false) => Some(services.describe(&self.service).await?),
+            (None, true) => services.describe(&self.service).await.ok(),
+            (Some(_), true) => None,
+        };
+        let tfn = service
+            .as_ref()
+            .and_then(|service| service.get(&self.fn_name));
+        if tfn.is_none() && !self.named.is_empty() {
+            return Err(Error::Other(format!(
+                "fn {} is not in service {}",
+                self.fn_name, self.service
+            )));
+        }
+        let declared = |i: usize| tfn.and_then(|tfn| tfn.args.get(i));
+
+        let mut args = match &self.json {
+            Some(json) => match serde_json::from_str(json)? {
+                Value::Object(args) => args,
+                _ => return Err(Error::Other("--json must be an object".to_owned())),
+            },
+            None => self
+                .args
+                .iter()
+                .enumerate()
+                .map(|(i, arg)| (i.to_string(), parse_value(declared(i), arg)))
+                .collect(),
+        };
+
+        if let Some(tfn) = tfn {
+            for named in &self.named {
+                let Some((name, value)) = named.split_once('=') else {
+                    return Err(Error::Other(format!("{named} should be NAME=VALUE")));
+                };
+                let Some(index) = tfn.args.iter().position(|arg| arg.name == name) else {
+                    return Err(Error::Other(format!("fn {} has no arg {name}", tfn.name)));
+                };
+                args.insert(index.to_string(), parse_value(declared(index), value));
+            }
+        }
+
+        // Transports take the names as static strings, as they usually come from generated code.
+        // This only runs once per process, so leaking them is fine.
+        Ok((
+            self.service.leak(),
+            DynCall {
+                fn_name: self.fn_name.leak(),
+                args,
+            },
+        ))
+    }
+}
+
+/// Reads an arg from the command line, using its declared type where it is known so that strings
+/// that look like JSON, such as `123`, are still sent as strings.
+fn parse_value(arg: Option<&ArgDescriptor<'_>>, value: &str) -> Value {
+    if arg.is_some_and(|arg| is_string_type(&arg.ty)) {
+        return Value::String(value.to_owned());
+    }
+    serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_owned()))
+}
+
+/// Whether a type name from a descriptor is one of the common string types, or an option of one.
+fn is_string_type(ty: &str) -> bool {
+    let ty = ty
+        .strip_prefix("Option<")
+        .and_then(|ty| ty.strip_suffix('>'))
+        .unwrap_or(ty);
+    let ty = ty.trim_start_matches('&');
+    // Drops the lifetime of a reference, such as the `'a` in `&'a str`
+    let ty = match ty.strip_prefix('\'') {
+        Some(ty) => ty.split_once(' ').map_or(ty, |(_, ty)| ty),
+        None => ty,
+    };
+    let name = ty.rsplit("::").next().unwrap_or(ty);
+
+    matches!(name, "String" | "str" | "char") || name.starts_with("Cow<") && name.ends_with(" str>")
+}
+
+fn read_schema(path: &Path) -> Result<Vec<ServiceDescriptor<'static>>, Error> {
+    let file = fs::read_to_string(path)
+        .map_err(|err| Error::Other(format!("failed to read {}: {err}", path.display())))?;
+
+    // Files can either hold a single descriptor or a list of them.
+    Ok(match serde_json::from_str(&file)? {
+        Value::Array(services) => services
+            .into_iter()
+            .map(serde_json::from_value)
+            .collect::<Result<_, _>>()?,
+        service => vec![serde_json::from_value(service)?],
+    })
+}
+
+fn print_service(service: &ServiceDescriptor<'_>) {
+    println!("{}", service.name);
+    print_docs(&service.docs, "  ");
+
+    for tfn in &*service.fns {
+        let args: Vec<_> = tfn
+            .args
+            .iter()
+            .map(|arg| format!("{}: {}", arg.name, arg.ty))
+            .collect();
+        let read_only = if tfn.read_only { " (read-only)" } else { "" };
+        let requires = tfn
+            .requires
+            .as_ref()
+            .map(|requires| format!(" (requires {requires})"))
+            .unwrap_or_default();
+
+        println!();
+        println!(
+            "  {}({}) -> {}{read_only}{requires}",
+            tfn.name,
+            args.join(", "),
+            tfn.returns
+        );
+        print_docs(&tfn.docs, "    ");
+    }
+}
+
+fn print_docs(docs: &str, indent: &str) {
+    for line in docs.lines() {
+        println!("{indent}{line}");
+    }
+}
+
+fn print_json(value: &Value) {
+    match serde_json::to_string_pretty(value) {
+        Ok(json) => println!("{json}"),
+        Err(_) => println!("{value}"),
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+enum Error {
+    #[error(transparent)]
+    Call(#[from] CallError),
+    #[error("invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("{0}")]
+    Other(String),
+}
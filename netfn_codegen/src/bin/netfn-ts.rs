@@ -0,0 +1,40 @@
+#![warn(clippy::pedantic)]
+
+//! Generates a TypeScript client from Rust files that define netfn services.
+//!
+//! Usage: `netfn-ts <file.rs>...`, with the client written to stdout.
+
+use std::{env, fs, process::ExitCode};
+
+fn main() -> ExitCode {
+    let paths: Vec<_> = env::args_os().skip(1).collect();
+    if paths.is_empty() {
+        eprintln!("usage: netfn-ts <file.rs>...");
+        return ExitCode::FAILURE;
+    }
+
+    let mut files = Vec::with_capacity(paths.len());
+    for path in &paths {
+        let file = fs::read_to_string(path)
+            .map_err(|err| err.to_string())
+            .and_then(|source| syn::parse_file(&source).map_err(|err| err.to_string()));
+        match file {
+            Ok(file) => files.push(file),
+            Err(err) => {
+                eprintln!("failed to read {}: {err}", path.display());
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    match netfn_codegen::typescript_generate(&files) {
+        Ok(client) => {
+            print!("{client}");
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("failed to generate client: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
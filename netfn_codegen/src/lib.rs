@@ -1,3 +1,5 @@
 mod service;
+mod typescript;
 
 pub use service::*;
+pub use typescript::*;
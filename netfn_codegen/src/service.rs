@@ -1,33 +1,104 @@
 #![warn(clippy::pedantic)]
-#![allow(clippy::similar_names)]
+#![allow(clippy::similar_names, clippy::needless_continue)]
 
 use case::CaseExt as _;
-use darling::{FromMeta, ast::NestedMeta};
+use darling::{FromAttributes, FromMeta, ast::NestedMeta};
 use proc_macro2::{Ident, TokenStream};
 use quote::{format_ident, quote, quote_spanned};
 use syn::{
-    Attribute, Error, FnArg, ItemTrait, Meta, PatType, Result, ReturnType, TraitItemFn, Visibility,
-    parse_quote, parse_quote_spanned, spanned::Spanned as _,
+    Attribute, Error, Expr, ExprLit, FnArg, ItemTrait, Lit, Meta, Pat, PatType, Result, ReturnType,
+    TraitItemFn, Visibility, parse_quote, parse_quote_spanned, spanned::Spanned as _,
 };
 
-#[derive(Debug, FromMeta)]
-struct Args {
+#[derive(Debug, Default, FromMeta)]
+pub(crate) struct Args {
     vis: Option<Visibility>,
+    /// Derives `JsonSchema` for the generated types, which needs the `schema` feature of netfn.
+    #[darling(default)]
+    schema: bool,
+    /// Generates a `Mock*` implementation of the trait, which needs the `mock` feature of netfn.
+    #[darling(default)]
+    mock: bool,
+    /// The name of the service, which defaults to the name of the trait.
+    ///
+    /// This allows several versions of a service to be defined as different traits.
+    name: Option<String>,
+    /// The version of the service, which is added to its name on the wire if it isn't 1.
+    version: Option<u32>,
+    /// The permission needed to call any fn that doesn't set its own.
+    requires: Option<String>,
+}
+
+impl Args {
+    /// Reads the args of a `#[service]` attribute.
+    pub(crate) fn from_attr(attr: &Attribute) -> Result<Self> {
+        match &attr.meta {
+            Meta::List(list) => Ok(Self::from_list(&NestedMeta::parse_meta_list(
+                list.tokens.clone(),
+            )?)?),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    pub(crate) fn version(&self) -> u32 {
+        self.version.unwrap_or(1)
+    }
+
+    /// The name that the service is called with, such as `UserApi.v2`.
+    pub(crate) fn wire_name(&self, typ: &Ident) -> String {
+        let name = self.name.clone().unwrap_or_else(|| typ.to_string());
+        match self.version() {
+            1 => name,
+            version => format!("{name}.v{version}"),
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.version == Some(0) {
+            return Err(Error::new(
+                proc_macro2::Span::call_site(),
+                "versions start at 1",
+            ));
+        }
+        if let Some(name) = &self.name
+            && (name.is_empty() || name.contains(['/', '.']))
+        {
+            return Err(Error::new(
+                proc_macro2::Span::call_site(),
+                "service names can't be empty or contain `/` or `.`",
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, FromAttributes)]
+#[darling(attributes(netfn))]
+struct FnArgs {
+    #[darling(default)]
+    read_only: bool,
+    requires: Option<String>,
 }
 
 // TODO: write up docs
 #[allow(clippy::missing_errors_doc)]
 pub fn service_generate(args: TokenStream, input: TokenStream) -> Result<TokenStream> {
     let args = Args::from_list(&NestedMeta::parse_meta_list(args)?)?;
+    args.validate()?;
     let item_trait: ItemTrait = syn::parse2(input)?;
 
-    let generator = Generator::new(&item_trait, args.vis.unwrap_or_else(|| parse_quote!(pub)))?;
-    Ok(generator.generate()?)
+    let generator = Generator::new(&item_trait, args)?;
+    generator.generate()
 }
 
 struct Generator<'a> {
     item_trait: &'a ItemTrait,
     vis: Visibility,
+    schema: bool,
+    mock: bool,
+    name: String,
+    version: u32,
+    requires: Option<String>,
     fns: Vec<ServiceFn>,
     ident_priv_mod: Ident,
     ident_container: Ident,
@@ -35,25 +106,32 @@ struct Generator<'a> {
     ident_req_enum: Ident,
     ident_res_enum: Ident,
     ident_client: Ident,
+    ident_mock: Ident,
 }
 
 impl<'a> Generator<'a> {
-    fn new(item_trait: &'a ItemTrait, vis: Visibility) -> Result<Self> {
+    fn new(item_trait: &'a ItemTrait, args: Args) -> Result<Self> {
         let typ = &item_trait.ident;
         Ok(Self {
             item_trait,
-            vis,
-            fns: Self::collect_fns(typ, item_trait),
+            name: args.wire_name(typ),
+            version: args.version(),
+            vis: args.vis.unwrap_or_else(|| parse_quote!(pub)),
+            schema: args.schema,
+            mock: args.mock,
+            requires: args.requires,
+            fns: Self::collect_fns(typ, item_trait)?,
             ident_priv_mod: Ident::new(&typ.to_string().to_snake(), typ.span()),
             ident_container: format_ident!("{}Container", &item_trait.ident),
             ident_ext_trait: format_ident!("{}Ext", &item_trait.ident),
             ident_req_enum: format_ident!("{}Request", typ),
             ident_res_enum: format_ident!("{}Response", typ),
             ident_client: format_ident!("{}Client", typ),
+            ident_mock: format_ident!("Mock{}", typ),
         })
     }
 
-    fn collect_fns(typ: &Ident, item_trait: &ItemTrait) -> Vec<ServiceFn> {
+    fn collect_fns(typ: &Ident, item_trait: &ItemTrait) -> Result<Vec<ServiceFn>> {
         item_trait
             .items
             .iter()
@@ -61,7 +139,13 @@ impl<'a> Generator<'a> {
                 syn::TraitItem::Fn(tfn) => Some(ServiceFn::new(typ, tfn)),
                 _ => None,
             })
-            .collect()
+            .collect::<darling::Result<_>>()
+            .map_err(Into::into)
+    }
+
+    /// The permission needed to call a fn, which falls back to the one set on the service.
+    fn requires<'b>(&'b self, tfn: &'b ServiceFn) -> Option<&'b str> {
+        tfn.fn_args.requires.as_deref().or(self.requires.as_deref())
     }
 
     fn generate(&self) -> Result<TokenStream> {
@@ -71,13 +155,20 @@ impl<'a> Generator<'a> {
         let req_enum = self.request_enum();
         let res_enum = self.response_enum();
         let client_impl = self.impl_service_client();
+        let descriptor = self.descriptor();
+        let schema = self.impl_schema();
+        let mock = self.mock_impl();
 
         let Self {
             ident_priv_mod,
             ident_client,
+            ident_mock,
             vis,
             ..
         } = self;
+        let mock_use = self
+            .mock
+            .then(|| quote!(#vis use self::#ident_priv_mod::#ident_mock;));
 
         Ok(quote! {
             #[allow(clippy::unused_async)]
@@ -95,8 +186,12 @@ impl<'a> Generator<'a> {
                 #req_enum
                 #res_enum
                 #client_impl
+                #descriptor
+                #schema
+                #mock
             }
             #vis use self::#ident_priv_mod::#ident_client;
+            #mock_use
         })
     }
 
@@ -104,17 +199,18 @@ impl<'a> Generator<'a> {
         let Self { item_trait, .. } = self;
         let mut item_trait = (*item_trait).clone();
 
-        for tfn in item_trait.items.iter_mut() {
+        for tfn in &mut item_trait.items {
             let syn::TraitItem::Fn(tfn) = tfn else {
                 continue;
             };
 
-            if let None = tfn.sig.asyncness {
+            if tfn.sig.asyncness.is_none() {
                 return Err(Error::new(tfn.span(), "Only async fns are supported"));
             }
             tfn.sig.asyncness = None;
+            tfn.attrs.retain(|attr| !attr.path().is_ident("netfn"));
 
-            let output = tfn_ret(&tfn);
+            let output = tfn_ret(tfn);
 
             tfn.sig.output = parse_quote_spanned! {output.span() =>
                 -> impl ::core::future::Future<Output = #output> + ::netfn::compat::NetfnSend
@@ -134,6 +230,8 @@ impl<'a> Generator<'a> {
             ident_ext_trait,
             ident_req_enum,
             ident_res_enum,
+            name,
+            version,
             ..
         } = self;
         let branches: Vec<_> = fns
@@ -154,17 +252,18 @@ impl<'a> Generator<'a> {
             .collect();
 
         let typ = &item_trait.ident;
-        let name = &item_trait.ident.to_string();
 
         let part_impl = quote! {
             const NAME: &'static str = SERVICE_NAME;
+            const DESCRIPTOR: ::netfn::ServiceDescriptor<'static> = DESCRIPTOR;
             type Request = #ident_priv_mod::#ident_req_enum;
             type Response = #ident_priv_mod::#ident_res_enum;
         };
 
         (
             quote! {
-                const SERVICE_NAME: &'static str = #name;
+                pub const SERVICE_NAME: &'static str = #name;
+                pub const SERVICE_VERSION: u32 = #version;
                 pub struct #ident_container<T>(pub T);
 
                 impl<T> ::netfn::Service for #ident_container<T> where T: #typ + ::netfn::compat::NetfnSync {
@@ -202,25 +301,27 @@ impl<'a> Generator<'a> {
 
     fn fn_inputs(&self) -> TokenStream {
         let Self { fns, .. } = self;
+        let schema_derive = self.schema_derives();
 
-        let inputs = fns.iter().filter_map(|tfn| {
+        let inputs = fns.iter().map(|tfn| {
             let name = &tfn.args;
             let args = tfn_args(&tfn.tfn).map(|(field, i, inp)| {
                 let derives = field_derives(i);
                 let ty = &inp.ty;
-                Some(quote! {
+                quote! {
                     #derives
                     pub #field: #ty
-                })
+                }
             });
             let derive = struct_derives();
 
-            Some(quote! {
+            quote! {
                 #derive
+                #schema_derive
                 pub struct #name {
                     #( #args ),*
                 }
-            })
+            }
         });
 
         quote! {
@@ -232,6 +333,7 @@ impl<'a> Generator<'a> {
         let Self {
             fns,
             ident_req_enum,
+            ident_res_enum,
             ..
         } = self;
 
@@ -244,14 +346,76 @@ impl<'a> Generator<'a> {
             }
         });
         let derive = struct_derives();
+        let schema_derive = self.schema_derives();
         let req_derive = request_derives();
 
+        let fn_names = fns.iter().map(|tfn| {
+            let ident = &tfn.variant;
+            let name = ident.to_string();
+            quote!(Self::#ident(_) => #name)
+        });
+        let read_only = fns.iter().map(|tfn| {
+            let ident = &tfn.variant;
+            let read_only = tfn.fn_args.read_only;
+            quote!(Self::#ident(_) => #read_only)
+        });
+        let requires = fns.iter().map(|tfn| {
+            let ident = &tfn.variant;
+            let requires = self.requires(tfn).map_or_else(
+                || quote!(::core::option::Option::None),
+                |requires| quote!(::core::option::Option::Some(#requires)),
+            );
+            quote!(Self::#ident(_) => #requires)
+        });
+        let decoders = fns.iter().map(|tfn| {
+            let ident = &tfn.variant;
+            let ret = tfn_ret(&tfn.tfn);
+            quote! {
+                Self::#ident(_) => <#ret as ::netfn::serde::Deserialize>::deserialize(deserializer)
+                    .map(#ident_res_enum::#ident)
+            }
+        });
+
         quote! {
             #derive
+            #schema_derive
             #req_derive
             pub enum #ident_req_enum {
                 #( #variants ),*
             }
+
+            impl ::netfn::ServiceRequest for #ident_req_enum {
+                fn fn_name(&self) -> &'static str {
+                    match *self {
+                        #( #fn_names ),*
+                    }
+                }
+
+                fn read_only(&self) -> bool {
+                    match *self {
+                        #( #read_only ),*
+                    }
+                }
+
+                fn requires(&self) -> ::core::option::Option<&'static str> {
+                    match *self {
+                        #( #requires ),*
+                    }
+                }
+            }
+
+            impl ::netfn::DecodeResponse for #ident_req_enum {
+                type Response = #ident_res_enum;
+
+                fn decode_response<'de, D>(&self, deserializer: D) -> ::core::result::Result<#ident_res_enum, D::Error>
+                where
+                    D: ::netfn::serde::Deserializer<'de>,
+                {
+                    match *self {
+                        #( #decoders ),*
+                    }
+                }
+            }
         }
     }
 
@@ -271,10 +435,14 @@ impl<'a> Generator<'a> {
             }
         });
         let derive = struct_derives();
+        let schema_derive = self.schema_derives();
         let res_derive = response_derives();
 
         quote! {
+            /// Deserializing this directly picks the first fn that returns a matching type, so
+            /// responses to a known request should be decoded with `DecodeResponse` instead.
             #derive
+            #schema_derive
             #res_derive
             pub enum #ident_res_enum {
                 #( #variants ),*
@@ -282,6 +450,204 @@ impl<'a> Generator<'a> {
         }
     }
 
+    fn descriptor(&self) -> TokenStream {
+        let Self {
+            item_trait, fns, ..
+        } = self;
+
+        let fn_descriptors = fns.iter().map(|tfn| {
+            let name = tfn.variant.to_string();
+            let docs = doc_string(&tfn.tfn.attrs);
+            let args = tfn_args(&tfn.tfn).map(|(_, i, inp)| {
+                let name = match &*inp.pat {
+                    Pat::Ident(pat) => pat.ident.to_string(),
+                    _ => i.to_string(),
+                };
+                let ty = type_name(&inp.ty);
+                quote! {
+                    ::netfn::ArgDescriptor {
+                        name: ::std::borrow::Cow::Borrowed(#name),
+                        ty: ::std::borrow::Cow::Borrowed(#ty),
+                    }
+                }
+            });
+            let returns = type_name(tfn_ret(&tfn.tfn));
+            let read_only = tfn.fn_args.read_only;
+            let requires = self.requires(tfn).map_or_else(
+                || quote!(::core::option::Option::None),
+                |requires| {
+                    quote!(::core::option::Option::Some(::std::borrow::Cow::Borrowed(#requires)))
+                },
+            );
+
+            quote! {
+                ::netfn::FnDescriptor {
+                    name: ::std::borrow::Cow::Borrowed(#name),
+                    docs: ::std::borrow::Cow::Borrowed(#docs),
+                    args: ::std::borrow::Cow::Borrowed(&[ #( #args ),* ]),
+                    returns: ::std::borrow::Cow::Borrowed(#returns),
+                    read_only: #read_only,
+                    requires: #requires,
+                }
+            }
+        });
+        let docs = doc_string(&item_trait.attrs);
+
+        quote! {
+            pub const DESCRIPTOR: ::netfn::ServiceDescriptor<'static> = ::netfn::ServiceDescriptor {
+                name: ::std::borrow::Cow::Borrowed(SERVICE_NAME),
+                version: SERVICE_VERSION,
+                docs: ::std::borrow::Cow::Borrowed(#docs),
+                fns: ::std::borrow::Cow::Borrowed(&[ #( #fn_descriptors ),* ]),
+            };
+        }
+    }
+
+    fn schema_derives(&self) -> TokenStream {
+        if !self.schema {
+            return TokenStream::new();
+        }
+
+        quote! {
+            #[derive(::netfn::schemars::JsonSchema)]
+            #[schemars(crate = "::netfn::schemars")]
+        }
+    }
+
+    fn impl_schema(&self) -> TokenStream {
+        let Self {
+            schema,
+            fns,
+            ident_req_enum,
+            ..
+        } = self;
+        if !schema {
+            return TokenStream::new();
+        }
+
+        let fn_schemas = fns.iter().map(|tfn| {
+            let name = tfn.variant.to_string();
+            let args = &tfn.args;
+            let ret = tfn_ret(&tfn.tfn);
+
+            quote! {
+                ::netfn::schema::FnSchema {
+                    name: #name,
+                    args: generator.subschema_for::<#args>(),
+                    returns: generator.subschema_for::<#ret>(),
+                }
+            }
+        });
+
+        quote! {
+            impl ::netfn::schema::ServiceSchema for #ident_req_enum {
+                const DESCRIPTOR: ::netfn::ServiceDescriptor<'static> = DESCRIPTOR;
+
+                fn fn_schemas(
+                    generator: &mut ::netfn::schemars::SchemaGenerator,
+                ) -> ::std::vec::Vec<::netfn::schema::FnSchema> {
+                    ::std::vec![ #( #fn_schemas ),* ]
+                }
+            }
+
+            /// Generates the JSON Schema document for this service.
+            pub fn schema() -> ::netfn::schemars::Schema {
+                ::netfn::schema::service_schema::<#ident_req_enum>()
+            }
+        }
+    }
+
+    fn mock_impl(&self) -> TokenStream {
+        let Self {
+            item_trait,
+            mock,
+            fns,
+            ident_mock,
+            ..
+        } = self;
+        if !mock {
+            return TokenStream::new();
+        }
+
+        let typ = &item_trait.ident;
+        let fields = fns.iter().map(|tfn| {
+            let name = &tfn.tfn.sig.ident;
+            let args = tfn_args(&tfn.tfn).map(|(_, _, inp)| &inp.ty);
+            let ret = tfn_ret(&tfn.tfn);
+            quote!(#name: ::netfn::mock::MockFn<( #( #args, )* ), #ret>)
+        });
+        let inits = fns.iter().map(|tfn| {
+            let name = &tfn.tfn.sig.ident;
+            let path = format!("{typ}::{name}");
+            quote!(#name: ::netfn::mock::MockFn::new(#path))
+        });
+        let expects = fns.iter().map(|tfn| {
+            let name = &tfn.tfn.sig.ident;
+            let expect = format_ident!("expect_{}", name);
+            let args = tfn_args(&tfn.tfn).map(|(_, _, inp)| &inp.ty);
+            let ret = tfn_ret(&tfn.tfn);
+            let doc = format!("Adds an expectation for calls to `{name}`.");
+            quote! {
+                #[doc = #doc]
+                pub fn #expect(&mut self) -> &mut ::netfn::mock::Expectation<( #( #args, )* ), #ret> {
+                    self.#name.expect()
+                }
+            }
+        });
+        let checkpoints = fns.iter().map(|tfn| {
+            let name = &tfn.tfn.sig.ident;
+            quote!(self.#name.checkpoint();)
+        });
+        let impls = fns.iter().map(|tfn| {
+            let name = &tfn.tfn.sig.ident;
+            let params = tfn_args(&tfn.tfn).map(|(i, _, inp)| {
+                let ty = &inp.ty;
+                quote!(#i: #ty)
+            });
+            let args = tfn_args(&tfn.tfn).map(|(i, _, _)| i);
+            let ret = tfn_ret(&tfn.tfn);
+            quote! {
+                fn #name(&self, #( #params ),*)
+                    -> impl ::core::future::Future<Output = #ret> + ::netfn::compat::NetfnSend {
+                    ::core::future::ready(self.#name.call(( #( #args, )* )))
+                }
+            }
+        });
+
+        quote! {
+            /// A mock of the service, which responds using the expectations set on it.
+            pub struct #ident_mock {
+                #( #fields ),*
+            }
+
+            impl #ident_mock {
+                pub fn new() -> Self {
+                    Self {
+                        #( #inits ),*
+                    }
+                }
+
+                #( #expects )*
+
+                /// Checks that every expectation with a set number of calls has been called that
+                /// many times, which is also done when the mock is dropped.
+                pub fn checkpoint(&self) {
+                    #( #checkpoints )*
+                }
+            }
+
+            impl ::core::default::Default for #ident_mock {
+                fn default() -> Self {
+                    Self::new()
+                }
+            }
+
+            impl #typ for #ident_mock {
+                #( #impls )*
+            }
+        }
+    }
+
     fn impl_service_client(&self) -> TokenStream {
         let Self {
             fns,
@@ -356,18 +722,20 @@ impl<'a> Generator<'a> {
 
 struct ServiceFn {
     tfn: TraitItemFn,
+    fn_args: FnArgs,
     variant: Ident,
     args: Ident,
 }
 
 impl ServiceFn {
-    fn new(typ: &Ident, tfn: &TraitItemFn) -> Self {
+    fn new(typ: &Ident, tfn: &TraitItemFn) -> darling::Result<Self> {
         let variant = Ident::new(&tfn.sig.ident.to_string().to_camel(), tfn.sig.ident.span());
-        Self {
+        Ok(Self {
             tfn: tfn.clone(),
+            fn_args: FnArgs::from_attributes(&tfn.attrs)?,
             args: format_ident!("{}{}Args", typ, variant),
             variant,
-        }
+        })
     }
 }
 
@@ -400,6 +768,46 @@ fn tfn_docs(tfn: &TraitItemFn) -> impl Iterator<Item = &Attribute> {
     })
 }
 
+/// Joins doc comments back into the text that was written, without the leading space that
+/// rustdoc adds to each line.
+pub(crate) fn doc_string(attrs: &[Attribute]) -> String {
+    attrs
+        .iter()
+        .filter_map(|attr| match &attr.meta {
+            Meta::NameValue(nv) if nv.path.is_ident("doc") => match &nv.value {
+                Expr::Lit(ExprLit {
+                    lit: Lit::Str(doc), ..
+                }) => Some(doc.value()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .map(|line| line.strip_prefix(' ').map(str::to_owned).unwrap_or(line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Renders a type as it would usually be written, as the token stream puts spaces between
+/// every token.
+fn type_name(ty: impl quote::ToTokens) -> String {
+    let tokens = ty.to_token_stream().to_string();
+    let chars: Vec<_> = tokens.chars().collect();
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+
+    let mut name = String::with_capacity(tokens.len());
+    for (i, c) in chars.iter().copied().enumerate() {
+        if c == ' ' {
+            let prev = name.chars().last().unwrap_or(' ');
+            let next = chars.get(i + 1).copied().unwrap_or(' ');
+            if !(prev == ',' || is_ident(prev) && (is_ident(next) || next == '\'')) {
+                continue;
+            }
+        }
+        name.push(c);
+    }
+    name
+}
+
 fn struct_derives() -> TokenStream {
     quote! {
         #[derive(::netfn::serde::Serialize, ::netfn::serde::Deserialize, Clone, Debug)]
@@ -0,0 +1,713 @@
+use std::{collections::BTreeSet, fmt::Write as _};
+
+use case::CaseExt as _;
+use syn::{
+    Attribute, Error, Fields, File, FnArg, GenericArgument, GenericParam, Generics, Item, ItemEnum,
+    ItemStruct, ItemTrait, Lit, Meta, Pat, PathArguments, Result, ReturnType, TraitItem, Type,
+    punctuated::Punctuated, spanned::Spanned as _,
+};
+
+use crate::service::{Args, doc_string};
+
+/// The transports shared by every generated client, following `docs/interface.md`.
+const RUNTIME: &str = r#"export interface GenericError {
+  code: string;
+  message: string;
+}
+
+export class NetfnError extends Error {
+  readonly code: string;
+
+  constructor(error: GenericError) {
+    super(error.message);
+    this.name = "NetfnError";
+    this.code = error.code;
+  }
+}
+
+export interface Transport {
+  call(service: string, fn: string, args: Record<string, unknown>): Promise<unknown>;
+}
+
+type StreamFrame =
+  | { type: "stream_message"; data: unknown }
+  | { type: "stream_close" }
+  | { type: "stream_error"; error: GenericError };
+
+export interface HttpTransportOptions {
+  headers?: Record<string, string>;
+  /** The status code the server uses for handler errors, which defaults to 537. */
+  handlerErrorStatus?: number;
+}
+
+/** Sends each call as a call-response request to a single HTTP endpoint. */
+export class HttpTransport implements Transport {
+  readonly #url: string;
+  readonly #options: HttpTransportOptions;
+
+  constructor(url: string, options: HttpTransportOptions = {}) {
+    this.#url = url;
+    this.#options = options;
+  }
+
+  async call(service: string, fn: string, args: Record<string, unknown>): Promise<unknown> {
+    const response = await this.#post(service, fn, args, "application/json");
+    return response.json();
+  }
+
+  /** Opens a stream on a stream service, which is sent back as newline-delimited JSON. */
+  async *stream(service: string, fn: string, args: Record<string, unknown>): AsyncGenerator<unknown> {
+    const response = await this.#post(service, fn, args, "application/x-ndjson");
+    if (response.body === null) {
+      return;
+    }
+
+    const reader = response.body.pipeThrough(new TextDecoderStream()).getReader();
+    let buffer = "";
+    try {
+      for (;;) {
+        const { done, value } = await reader.read();
+        if (done) {
+          return;
+        }
+
+        buffer += value;
+        let end;
+        while ((end = buffer.indexOf("\n")) !== -1) {
+          const line = buffer.slice(0, end).trim();
+          buffer = buffer.slice(end + 1);
+          if (line === "") {
+            continue;
+          }
+
+          const frame: StreamFrame = JSON.parse(line);
+          switch (frame.type) {
+            case "stream_message":
+              yield frame.data;
+              break;
+            case "stream_close":
+              return;
+            case "stream_error":
+              throw new NetfnError(frame.error);
+          }
+        }
+      }
+    } finally {
+      await reader.cancel();
+    }
+  }
+
+  async #post(
+    service: string,
+    fn: string,
+    args: Record<string, unknown>,
+    accept: string,
+  ): Promise<Response> {
+    const response = await fetch(this.#url, {
+      method: "POST",
+      headers: { ...this.#options.headers, "Content-Type": "application/json", Accept: accept },
+      body: JSON.stringify({ service, call: { fn, args } }),
+    });
+
+    if (response.status === (this.#options.handlerErrorStatus ?? 537)) {
+      throw new NetfnError(await response.json());
+    }
+    if (!response.ok) {
+      throw new Error(`request failed with status ${response.status}`);
+    }
+    return response;
+  }
+}
+
+interface PendingCall {
+  resolve: (data: unknown) => void;
+  reject: (err: Error) => void;
+}
+
+/** Holds the frames of a tunnel stream until they are read, or the error that closed the tunnel. */
+class StreamQueue {
+  readonly #frames: (StreamFrame | Error)[] = [];
+  #wake: (() => void) | undefined;
+
+  push(frame: StreamFrame | Error) {
+    this.#frames.push(frame);
+    this.#wake?.();
+    this.#wake = undefined;
+  }
+
+  async next(): Promise<StreamFrame | Error> {
+    while (this.#frames.length === 0) {
+      await new Promise<void>((resolve) => (this.#wake = resolve));
+    }
+    return this.#frames.shift()!;
+  }
+}
+
+/** Sends calls and streams through a tunnel over an open WebSocket, using JSON text messages. */
+export class WebSocketTransport implements Transport {
+  readonly #socket: WebSocket;
+  readonly #pending = new Map<number, PendingCall>();
+  readonly #streams = new Map<number, StreamQueue>();
+  #ref = 0;
+
+  constructor(socket: WebSocket) {
+    this.#socket = socket;
+    socket.addEventListener("message", (event) => this.#receive(event));
+    socket.addEventListener("close", () => this.#close());
+  }
+
+  call(service: string, fn: string, args: Record<string, unknown>): Promise<unknown> {
+    const ref = this.#ref++;
+    return new Promise((resolve, reject) => {
+      this.#pending.set(ref, { resolve, reject });
+      this.#socket.send(JSON.stringify({ type: "request", ref, service, call: { fn, args } }));
+    });
+  }
+
+  /**
+   * Opens a stream on a stream service. Closing the stream early, such as by breaking out of a
+   * `for await` loop, closes it on the server too.
+   */
+  async *stream(service: string, fn: string, args: Record<string, unknown>): AsyncGenerator<unknown> {
+    const ref = this.#ref++;
+    const [handle, queue] = (await new Promise((resolve, reject) => {
+      this.#pending.set(ref, { resolve, reject });
+      this.#socket.send(JSON.stringify({ type: "stream_open", ref, service, call: { fn, args } }));
+    })) as [number, StreamQueue];
+
+    let open = true;
+    try {
+      for (;;) {
+        const frame = await queue.next();
+        if (frame instanceof Error) {
+          open = false;
+          throw frame;
+        }
+
+        switch (frame.type) {
+          case "stream_message":
+            yield frame.data;
+            break;
+          case "stream_close":
+            open = false;
+            return;
+          case "stream_error":
+            open = false;
+            throw new NetfnError(frame.error);
+        }
+      }
+    } finally {
+      this.#streams.delete(handle);
+      if (open) {
+        this.#socket.send(JSON.stringify({ type: "stream_close", handle }));
+      }
+    }
+  }
+
+  #receive(event: MessageEvent) {
+    if (typeof event.data !== "string") {
+      return;
+    }
+
+    const message = JSON.parse(event.data);
+    switch (message.type) {
+      case "stream_message":
+      case "stream_close":
+      case "stream_error":
+        this.#streams.get(message.handle)?.push(message);
+        return;
+    }
+
+    const pending = this.#pending.get(message.ref);
+    if (pending === undefined) {
+      return;
+    }
+
+    switch (message.type) {
+      case "response":
+        pending.resolve(message.data);
+        break;
+      case "error":
+      case "stream_open_error":
+        pending.reject(new NetfnError(message.error));
+        break;
+      case "stream_ready": {
+        // The queue is added here so that no messages are missed before the stream is read
+        const queue = new StreamQueue();
+        this.#streams.set(message.handle, queue);
+        pending.resolve([message.handle, queue]);
+        break;
+      }
+      default:
+        return;
+    }
+    this.#pending.delete(message.ref);
+  }
+
+  #close() {
+    for (const pending of this.#pending.values()) {
+      pending.reject(new Error("tunnel closed"));
+    }
+    this.#pending.clear();
+    for (const queue of this.#streams.values()) {
+      queue.push(new Error("tunnel closed"));
+    }
+    this.#streams.clear();
+  }
+}
+"#;
+
+/// Generates a TypeScript client for every service in the given files.
+///
+/// Services are found by their `#[service]` attribute, and any structs and enums in the files
+/// are converted into types following serde's representation of them. Types that are used but
+/// can't be found are declared as `unknown`.
+#[allow(clippy::missing_errors_doc)]
+pub fn typescript_generate(files: &[File]) -> Result<String> {
+    let items: Vec<_> = files.iter().flat_map(|file| &file.items).collect();
+    let mut generator = TsGenerator {
+        known: items
+            .iter()
+            .filter_map(|item| match item {
+                Item::Struct(item) if derives_serde(&item.attrs) => Some(item.ident.to_string()),
+                Item::Enum(item) if derives_serde(&item.attrs) => Some(item.ident.to_string()),
+                _ => None,
+            })
+            .collect(),
+        unknown: BTreeSet::new(),
+        params: Vec::new(),
+    };
+
+    let mut out = String::from("// Generated by netfn. Do not edit by hand.\n\n");
+    out.push_str(RUNTIME);
+
+    for item in &items {
+        let decl = match item {
+            Item::Struct(item) if derives_serde(&item.attrs) => generator.struct_decl(item)?,
+            Item::Enum(item) if derives_serde(&item.attrs) => generator.enum_decl(item)?,
+            _ => continue,
+        };
+        write!(out, "\n{decl}").unwrap();
+    }
+
+    for item in &items {
+        let Item::Trait(item) = item else {
+            continue;
+        };
+        if let Some(attr) = item.attrs.iter().find(|attr| {
+            attr.path()
+                .segments
+                .last()
+                .is_some_and(|seg| seg.ident == "service")
+        }) {
+            let service = Args::from_attr(attr)?.wire_name(&item.ident);
+            let client = generator.client(item, &service)?;
+            write!(out, "\n{client}").unwrap();
+        }
+    }
+
+    for name in &generator.unknown {
+        write!(
+            out,
+            "\n/** Not found in the Rust sources. */\nexport type {name} = unknown;\n"
+        )
+        .unwrap();
+    }
+
+    Ok(out)
+}
+
+struct TsGenerator {
+    known: BTreeSet<String>,
+    unknown: BTreeSet<String>,
+    /// The generic params of the type being declared.
+    params: Vec<String>,
+}
+
+impl TsGenerator {
+    fn client(&mut self, item: &ItemTrait, service: &str) -> Result<String> {
+        let mut out = String::new();
+        self.params.clear();
+
+        out.push_str(&ts_docs(&item.attrs, ""));
+        writeln!(out, "export class {}Client {{", item.ident).unwrap();
+        writeln!(out, "  static readonly SERVICE_NAME = {service:?};\n").unwrap();
+        writeln!(out, "  readonly #transport: Transport;\n").unwrap();
+        writeln!(out, "  constructor(transport: Transport) {{").unwrap();
+        writeln!(out, "    this.#transport = transport;").unwrap();
+        writeln!(out, "  }}").unwrap();
+
+        for item in &item.items {
+            let TraitItem::Fn(tfn) = item else {
+                continue;
+            };
+
+            let name = tfn.sig.ident.to_string();
+            let mut params = Vec::new();
+            let mut args = Vec::new();
+            for (i, inp) in tfn
+                .sig
+                .inputs
+                .iter()
+                .filter_map(|inp| match inp {
+                    FnArg::Receiver(_) => None,
+                    FnArg::Typed(inp) => Some(inp),
+                })
+                .enumerate()
+            {
+                let param = match &*inp.pat {
+                    Pat::Ident(pat) => pat.ident.to_string().to_camel_lowercase(),
+                    _ => format!("arg{i}"),
+                };
+                params.push(format!("{param}: {}", self.ts_type(&inp.ty)?));
+                args.push(format!("\"{i}\": {param}"));
+            }
+            let returns = match &tfn.sig.output {
+                ReturnType::Default => "null".to_owned(),
+                ReturnType::Type(_, ty) => self.ts_type(ty)?,
+            };
+
+            out.push('\n');
+            out.push_str(&ts_docs(&tfn.attrs, "  "));
+            writeln!(
+                out,
+                "  {}({}): Promise<{returns}> {{",
+                name.to_camel_lowercase(),
+                params.join(", ")
+            )
+            .unwrap();
+            let args = if args.is_empty() {
+                "{}".to_owned()
+            } else {
+                format!("{{ {} }}", args.join(", "))
+            };
+            writeln!(
+                out,
+                "    return this.#transport.call({service:?}, {:?}, {args}) as Promise<{returns}>;",
+                name.to_camel(),
+            )
+            .unwrap();
+            writeln!(out, "  }}").unwrap();
+        }
+
+        out.push_str("}\n");
+        Ok(out)
+    }
+
+    fn struct_decl(&mut self, item: &ItemStruct) -> Result<String> {
+        let serde = SerdeAttrs::new(&item.attrs)?;
+        self.params = ts_generics(&item.generics);
+        let name = type_name(&item.ident.to_string(), &self.params);
+        let body = match &item.fields {
+            Fields::Named(_) => self.fields_type(&item.fields, serde.rename_all.as_deref(), "")?,
+            Fields::Unnamed(fields) if fields.unnamed.len() == 1 => {
+                self.ts_type(&fields.unnamed[0].ty)?
+            }
+            Fields::Unnamed(fields) => self.tuple_type(fields.unnamed.iter().map(|f| &f.ty))?,
+            Fields::Unit => "null".to_owned(),
+        };
+
+        Ok(format!(
+            "{}export type {name} = {body};\n",
+            ts_docs(&item.attrs, "")
+        ))
+    }
+
+    fn enum_decl(&mut self, item: &ItemEnum) -> Result<String> {
+        let serde = SerdeAttrs::new(&item.attrs)?;
+        self.params = ts_generics(&item.generics);
+        let name = type_name(&item.ident.to_string(), &self.params);
+
+        let mut variants = Vec::new();
+        for variant in &item.variants {
+            let attrs = SerdeAttrs::new(&variant.attrs)?;
+            if attrs.skip {
+                continue;
+            }
+            let wire_name = attrs.rename.unwrap_or_else(|| {
+                rename_variant(&variant.ident.to_string(), serde.rename_all.as_deref())
+            });
+            let rename_all = attrs.rename_all.as_deref();
+
+            let data = match &variant.fields {
+                Fields::Unit => None,
+                Fields::Unnamed(fields) if fields.unnamed.len() == 1 => {
+                    Some(self.ts_type(&fields.unnamed[0].ty)?)
+                }
+                Fields::Unnamed(fields) => {
+                    Some(self.tuple_type(fields.unnamed.iter().map(|f| &f.ty))?)
+                }
+                Fields::Named(_) => Some(self.fields_type(&variant.fields, rename_all, "  ")?),
+            };
+
+            variants.push(match (&serde.tag, &serde.content, serde.untagged, data) {
+                (_, _, true, data) => data.unwrap_or_else(|| "null".to_owned()),
+                (Some(tag), Some(content), _, Some(data)) => {
+                    format!("{{ {tag:?}: {wire_name:?}; {content:?}: {data} }}")
+                }
+                (Some(tag), _, _, None) => format!("{{ {tag:?}: {wire_name:?} }}"),
+                (Some(tag), None, _, Some(data)) => {
+                    format!("{{ {tag:?}: {wire_name:?} }} & {data}")
+                }
+                (None, _, _, Some(data)) => format!("{{ {wire_name:?}: {data} }}"),
+                (None, _, _, None) => format!("{wire_name:?}"),
+            });
+        }
+
+        Ok(format!(
+            "{}export type {name} =\n  | {};\n",
+            ts_docs(&item.attrs, ""),
+            variants.join("\n  | ")
+        ))
+    }
+
+    fn fields_type(
+        &mut self,
+        fields: &Fields,
+        rename_all: Option<&str>,
+        indent: &str,
+    ) -> Result<String> {
+        let mut out = String::from("{\n");
+        for field in fields {
+            let attrs = SerdeAttrs::new(&field.attrs)?;
+            if attrs.skip {
+                continue;
+            }
+            let Some(ident) = &field.ident else {
+                continue;
+            };
+            let name = attrs
+                .rename
+                .unwrap_or_else(|| rename_field(&ident.to_string(), rename_all));
+            let optional = if attrs.optional { "?" } else { "" };
+
+            out.push_str(&ts_docs(&field.attrs, &format!("{indent}  ")));
+            writeln!(
+                out,
+                "{indent}  {name:?}{optional}: {};",
+                self.ts_type(&field.ty)?
+            )
+            .unwrap();
+        }
+        write!(out, "{indent}}}").unwrap();
+        Ok(out)
+    }
+
+    fn tuple_type<'a>(&mut self, types: impl Iterator<Item = &'a Type>) -> Result<String> {
+        let types = types
+            .map(|ty| self.ts_type(ty))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(format!("[{}]", types.join(", ")))
+    }
+
+    fn ts_type(&mut self, ty: &Type) -> Result<String> {
+        Ok(match ty {
+            Type::Paren(ty) => self.ts_type(&ty.elem)?,
+            Type::Group(ty) => self.ts_type(&ty.elem)?,
+            Type::Reference(ty) => self.ts_type(&ty.elem)?,
+            Type::Slice(ty) => format!("{}[]", self.array_elem(&ty.elem)?),
+            Type::Array(ty) => format!("{}[]", self.array_elem(&ty.elem)?),
+            Type::Tuple(ty) if ty.elems.is_empty() => "null".to_owned(),
+            Type::Tuple(ty) => self.tuple_type(ty.elems.iter())?,
+            Type::Path(path) if path.qself.is_none() => {
+                let seg = path
+                    .path
+                    .segments
+                    .last()
+                    .ok_or_else(|| Error::new(ty.span(), "empty type path"))?;
+                let args = match &seg.arguments {
+                    PathArguments::AngleBracketed(args) => args
+                        .args
+                        .iter()
+                        .filter_map(|arg| match arg {
+                            GenericArgument::Type(ty) => Some(ty),
+                            _ => None,
+                        })
+                        .collect(),
+                    _ => Vec::new(),
+                };
+                self.path_type(&seg.ident.to_string(), &args)?
+            }
+            _ => {
+                return Err(Error::new(
+                    ty.span(),
+                    "type cannot be converted to TypeScript",
+                ));
+            }
+        })
+    }
+
+    fn array_elem(&mut self, ty: &Type) -> Result<String> {
+        let elem = self.ts_type(ty)?;
+        Ok(if elem.contains(' ') {
+            format!("({elem})")
+        } else {
+            elem
+        })
+    }
+
+    fn path_type(&mut self, name: &str, args: &[&Type]) -> Result<String> {
+        let arg = |this: &mut Self, i: usize| -> Result<String> {
+            match args.get(i) {
+                Some(ty) => this.ts_type(ty),
+                None => Ok("unknown".to_owned()),
+            }
+        };
+
+        Ok(match name {
+            "bool" => "boolean".to_owned(),
+            "u8" | "u16" | "u32" | "u64" | "u128" | "usize" | "i8" | "i16" | "i32" | "i64"
+            | "i128" | "isize" | "f32" | "f64" => "number".to_owned(),
+            "char" | "str" | "String" => "string".to_owned(),
+            "Value" => "unknown".to_owned(),
+            "Box" | "Rc" | "Arc" | "Cow" => arg(self, 0)?,
+            "Option" => format!("{} | null", arg(self, 0)?),
+            "Vec" | "VecDeque" | "HashSet" | "BTreeSet" => match args.first() {
+                Some(ty) => format!("{}[]", self.array_elem(ty)?),
+                None => "unknown[]".to_owned(),
+            },
+            "HashMap" | "BTreeMap" => format!("Record<string, {}>", arg(self, 1)?),
+            "Result" => format!("{{ Ok: {} }} | {{ Err: {} }}", arg(self, 0)?, arg(self, 1)?),
+            _ if self.params.iter().any(|param| param == name) => name.to_owned(),
+            _ => {
+                if !self.known.contains(name) {
+                    self.unknown.insert(name.to_owned());
+                }
+                let args = args
+                    .iter()
+                    .map(|ty| self.ts_type(ty))
+                    .collect::<Result<Vec<_>>>()?;
+                type_name(name, &args)
+            }
+        })
+    }
+}
+
+/// The serde attributes that change how a type is represented.
+#[derive(Default)]
+struct SerdeAttrs {
+    rename: Option<String>,
+    rename_all: Option<String>,
+    tag: Option<String>,
+    content: Option<String>,
+    untagged: bool,
+    skip: bool,
+    optional: bool,
+}
+
+impl SerdeAttrs {
+    fn new(attrs: &[Attribute]) -> Result<Self> {
+        let mut this = Self::default();
+        for attr in attrs.iter().filter(|attr| attr.path().is_ident("serde")) {
+            let metas =
+                attr.parse_args_with(Punctuated::<Meta, syn::Token![,]>::parse_terminated)?;
+            for meta in metas {
+                let value = match &meta {
+                    Meta::NameValue(nv) => match &nv.value {
+                        syn::Expr::Lit(lit) => match &lit.lit {
+                            Lit::Str(lit) => Some(lit.value()),
+                            _ => None,
+                        },
+                        _ => None,
+                    },
+                    _ => None,
+                };
+
+                let Some(ident) = meta.path().get_ident() else {
+                    continue;
+                };
+                match ident.to_string().as_str() {
+                    "rename" => this.rename = value,
+                    "rename_all" => this.rename_all = value,
+                    "tag" => this.tag = value,
+                    "content" => this.content = value,
+                    "untagged" => this.untagged = true,
+                    "skip" | "skip_serializing" => this.skip = true,
+                    "default" | "skip_serializing_if" => this.optional = true,
+                    _ => {}
+                }
+            }
+        }
+        Ok(this)
+    }
+}
+
+/// Whether a type derives either of serde's traits, which is what makes it part of the API.
+fn derives_serde(attrs: &[Attribute]) -> bool {
+    attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("derive"))
+        .filter_map(|attr| {
+            attr.parse_args_with(Punctuated::<syn::Path, syn::Token![,]>::parse_terminated)
+                .ok()
+        })
+        .flatten()
+        .any(|path| {
+            path.segments
+                .last()
+                .is_some_and(|seg| seg.ident == "Serialize" || seg.ident == "Deserialize")
+        })
+}
+
+fn ts_generics(generics: &Generics) -> Vec<String> {
+    generics
+        .params
+        .iter()
+        .filter_map(|param| match param {
+            GenericParam::Type(param) => Some(param.ident.to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn type_name(name: &str, args: &[String]) -> String {
+    if args.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{name}<{}>", args.join(", "))
+    }
+}
+
+fn ts_docs(attrs: &[Attribute], indent: &str) -> String {
+    let docs = doc_string(attrs);
+    if docs.is_empty() {
+        return String::new();
+    }
+
+    let mut out = format!("{indent}/**\n");
+    for line in docs.lines() {
+        if line.is_empty() {
+            writeln!(out, "{indent} *").unwrap();
+        } else {
+            writeln!(out, "{indent} * {line}").unwrap();
+        }
+    }
+    writeln!(out, "{indent} */").unwrap();
+    out
+}
+
+/// Applies a serde `rename_all` rule to a variant name, which starts out in `PascalCase`.
+fn rename_variant(name: &str, rule: Option<&str>) -> String {
+    match rule {
+        Some("lowercase") => name.to_ascii_lowercase(),
+        Some("UPPERCASE") => name.to_ascii_uppercase(),
+        Some("camelCase") => name.to_camel_lowercase(),
+        Some("snake_case") => name.to_snake(),
+        Some("SCREAMING_SNAKE_CASE") => name.to_snake().to_ascii_uppercase(),
+        Some("kebab-case") => name.to_snake().replace('_', "-"),
+        Some("SCREAMING-KEBAB-CASE") => name.to_snake().replace('_', "-").to_ascii_uppercase(),
+        _ => name.to_owned(),
+    }
+}
+
+/// Applies a serde `rename_all` rule to a field name, which starts out in `snake_case`.
+fn rename_field(name: &str, rule: Option<&str>) -> String {
+    match rule {
+        Some("UPPERCASE" | "SCREAMING_SNAKE_CASE") => name.to_ascii_uppercase(),
+        Some("PascalCase") => name.to_camel(),
+        Some("camelCase") => name.to_camel_lowercase(),
+        Some("kebab-case") => name.replace('_', "-"),
+        Some("SCREAMING-KEBAB-CASE") => name.replace('_', "-").to_ascii_uppercase(),
+        _ => name.to_owned(),
+    }
+}
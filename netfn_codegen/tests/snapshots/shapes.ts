@@ -0,0 +1,298 @@
+// Generated by netfn. Do not edit by hand.
+
+export interface GenericError {
+  code: string;
+  message: string;
+}
+
+export class NetfnError extends Error {
+  readonly code: string;
+
+  constructor(error: GenericError) {
+    super(error.message);
+    this.name = "NetfnError";
+    this.code = error.code;
+  }
+}
+
+export interface Transport {
+  call(service: string, fn: string, args: Record<string, unknown>): Promise<unknown>;
+}
+
+type StreamFrame =
+  | { type: "stream_message"; data: unknown }
+  | { type: "stream_close" }
+  | { type: "stream_error"; error: GenericError };
+
+export interface HttpTransportOptions {
+  headers?: Record<string, string>;
+  /** The status code the server uses for handler errors, which defaults to 537. */
+  handlerErrorStatus?: number;
+}
+
+/** Sends each call as a call-response request to a single HTTP endpoint. */
+export class HttpTransport implements Transport {
+  readonly #url: string;
+  readonly #options: HttpTransportOptions;
+
+  constructor(url: string, options: HttpTransportOptions = {}) {
+    this.#url = url;
+    this.#options = options;
+  }
+
+  async call(service: string, fn: string, args: Record<string, unknown>): Promise<unknown> {
+    const response = await this.#post(service, fn, args, "application/json");
+    return response.json();
+  }
+
+  /** Opens a stream on a stream service, which is sent back as newline-delimited JSON. */
+  async *stream(service: string, fn: string, args: Record<string, unknown>): AsyncGenerator<unknown> {
+    const response = await this.#post(service, fn, args, "application/x-ndjson");
+    if (response.body === null) {
+      return;
+    }
+
+    const reader = response.body.pipeThrough(new TextDecoderStream()).getReader();
+    let buffer = "";
+    try {
+      for (;;) {
+        const { done, value } = await reader.read();
+        if (done) {
+          return;
+        }
+
+        buffer += value;
+        let end;
+        while ((end = buffer.indexOf("\n")) !== -1) {
+          const line = buffer.slice(0, end).trim();
+          buffer = buffer.slice(end + 1);
+          if (line === "") {
+            continue;
+          }
+
+          const frame: StreamFrame = JSON.parse(line);
+          switch (frame.type) {
+            case "stream_message":
+              yield frame.data;
+              break;
+            case "stream_close":
+              return;
+            case "stream_error":
+              throw new NetfnError(frame.error);
+          }
+        }
+      }
+    } finally {
+      await reader.cancel();
+    }
+  }
+
+  async #post(
+    service: string,
+    fn: string,
+    args: Record<string, unknown>,
+    accept: string,
+  ): Promise<Response> {
+    const response = await fetch(this.#url, {
+      method: "POST",
+      headers: { ...this.#options.headers, "Content-Type": "application/json", Accept: accept },
+      body: JSON.stringify({ service, call: { fn, args } }),
+    });
+
+    if (response.status === (this.#options.handlerErrorStatus ?? 537)) {
+      throw new NetfnError(await response.json());
+    }
+    if (!response.ok) {
+      throw new Error(`request failed with status ${response.status}`);
+    }
+    return response;
+  }
+}
+
+interface PendingCall {
+  resolve: (data: unknown) => void;
+  reject: (err: Error) => void;
+}
+
+/** Holds the frames of a tunnel stream until they are read, or the error that closed the tunnel. */
+class StreamQueue {
+  readonly #frames: (StreamFrame | Error)[] = [];
+  #wake: (() => void) | undefined;
+
+  push(frame: StreamFrame | Error) {
+    this.#frames.push(frame);
+    this.#wake?.();
+    this.#wake = undefined;
+  }
+
+  async next(): Promise<StreamFrame | Error> {
+    while (this.#frames.length === 0) {
+      await new Promise<void>((resolve) => (this.#wake = resolve));
+    }
+    return this.#frames.shift()!;
+  }
+}
+
+/** Sends calls and streams through a tunnel over an open WebSocket, using JSON text messages. */
+export class WebSocketTransport implements Transport {
+  readonly #socket: WebSocket;
+  readonly #pending = new Map<number, PendingCall>();
+  readonly #streams = new Map<number, StreamQueue>();
+  #ref = 0;
+
+  constructor(socket: WebSocket) {
+    this.#socket = socket;
+    socket.addEventListener("message", (event) => this.#receive(event));
+    socket.addEventListener("close", () => this.#close());
+  }
+
+  call(service: string, fn: string, args: Record<string, unknown>): Promise<unknown> {
+    const ref = this.#ref++;
+    return new Promise((resolve, reject) => {
+      this.#pending.set(ref, { resolve, reject });
+      this.#socket.send(JSON.stringify({ type: "request", ref, service, call: { fn, args } }));
+    });
+  }
+
+  /**
+   * Opens a stream on a stream service. Closing the stream early, such as by breaking out of a
+   * `for await` loop, closes it on the server too.
+   */
+  async *stream(service: string, fn: string, args: Record<string, unknown>): AsyncGenerator<unknown> {
+    const ref = this.#ref++;
+    const [handle, queue] = (await new Promise((resolve, reject) => {
+      this.#pending.set(ref, { resolve, reject });
+      this.#socket.send(JSON.stringify({ type: "stream_open", ref, service, call: { fn, args } }));
+    })) as [number, StreamQueue];
+
+    let open = true;
+    try {
+      for (;;) {
+        const frame = await queue.next();
+        if (frame instanceof Error) {
+          open = false;
+          throw frame;
+        }
+
+        switch (frame.type) {
+          case "stream_message":
+            yield frame.data;
+            break;
+          case "stream_close":
+            open = false;
+            return;
+          case "stream_error":
+            open = false;
+            throw new NetfnError(frame.error);
+        }
+      }
+    } finally {
+      this.#streams.delete(handle);
+      if (open) {
+        this.#socket.send(JSON.stringify({ type: "stream_close", handle }));
+      }
+    }
+  }
+
+  #receive(event: MessageEvent) {
+    if (typeof event.data !== "string") {
+      return;
+    }
+
+    const message = JSON.parse(event.data);
+    switch (message.type) {
+      case "stream_message":
+      case "stream_close":
+      case "stream_error":
+        this.#streams.get(message.handle)?.push(message);
+        return;
+    }
+
+    const pending = this.#pending.get(message.ref);
+    if (pending === undefined) {
+      return;
+    }
+
+    switch (message.type) {
+      case "response":
+        pending.resolve(message.data);
+        break;
+      case "error":
+      case "stream_open_error":
+        pending.reject(new NetfnError(message.error));
+        break;
+      case "stream_ready": {
+        // The queue is added here so that no messages are missed before the stream is read
+        const queue = new StreamQueue();
+        this.#streams.set(message.handle, queue);
+        pending.resolve([message.handle, queue]);
+        break;
+      }
+      default:
+        return;
+    }
+    this.#pending.delete(message.ref);
+  }
+
+  #close() {
+    for (const pending of this.#pending.values()) {
+      pending.reject(new Error("tunnel closed"));
+    }
+    this.#pending.clear();
+    for (const queue of this.#streams.values()) {
+      queue.push(new Error("tunnel closed"));
+    }
+    this.#streams.clear();
+  }
+}
+
+/**
+ * A point on a grid.
+ */
+export type Point = {
+  "posX": number;
+  "posY": number;
+  "label"?: string | null;
+};
+
+export type Shape =
+  | { "type": "dot" } & Point
+  | { "type": "line" } & {
+    "from": Point;
+    "to": Point;
+  }
+  | { "type": "empty" };
+
+export class ShapesClient {
+  static readonly SERVICE_NAME = "Shapes";
+
+  readonly #transport: Transport;
+
+  constructor(transport: Transport) {
+    this.#transport = transport;
+  }
+
+  /**
+   * Moves a shape by an offset.
+   *
+   * Empty shapes are returned unchanged.
+   */
+  moveShape(shape: Shape, by: Point): Promise<Shape> {
+    return this.#transport.call("Shapes", "MoveShape", { "0": shape, "1": by }) as Promise<Shape>;
+  }
+
+  list(): Promise<Shape[]> {
+    return this.#transport.call("Shapes", "List", {}) as Promise<Shape[]>;
+  }
+
+  clear(): Promise<null> {
+    return this.#transport.call("Shapes", "Clear", {}) as Promise<null>;
+  }
+
+  lookup(id: Uuid): Promise<{ Ok: Shape } | { Err: string }> {
+    return this.#transport.call("Shapes", "Lookup", { "0": id }) as Promise<{ Ok: Shape } | { Err: string }>;
+  }
+}
+
+/** Not found in the Rust sources. */
+export type Uuid = unknown;
@@ -0,0 +1,61 @@
+use std::{env, fs, path::Path};
+
+const SOURCE: &str = r#"
+use serde::{Deserialize, Serialize};
+
+/// A point on a grid.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Point {
+    pub pos_x: i32,
+    pub pos_y: i32,
+    #[serde(default)]
+    pub label: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Shape {
+    Dot(Point),
+    Line { from: Point, to: Point },
+    Empty,
+}
+
+/// Not part of the API, so it is skipped.
+pub struct Canvas;
+
+#[netfn::service]
+pub trait Shapes {
+    /// Moves a shape by an offset.
+    ///
+    /// Empty shapes are returned unchanged.
+    async fn move_shape(&self, shape: Shape, by: Point) -> Shape;
+
+    async fn list(&self) -> Vec<Shape>;
+
+    async fn clear(&self);
+
+    async fn lookup(&self, id: Uuid) -> Result<Shape, String>;
+}
+"#;
+
+/// Compares the generated client with the snapshot, which is rewritten instead when
+/// `NETFN_UPDATE_SNAPSHOTS` is set.
+#[test]
+fn typescript_client() {
+    let file = syn::parse_file(SOURCE).unwrap();
+    let client = netfn_codegen::typescript_generate(&[file]).unwrap();
+
+    let snapshot = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots/shapes.ts");
+    if env::var_os("NETFN_UPDATE_SNAPSHOTS").is_some() {
+        fs::write(&snapshot, &client).unwrap();
+        return;
+    }
+
+    let expected = fs::read_to_string(&snapshot).unwrap();
+    assert!(
+        client == expected,
+        "the generated client doesn't match {}, run with NETFN_UPDATE_SNAPSHOTS=1 to update it\n\n{client}",
+        snapshot.display(),
+    );
+}
@@ -0,0 +1,125 @@
+use netfn::{GenericError, ServiceRequest};
+use serde::{Deserialize, Serialize, ser::SerializeMap as _};
+use serde_json::Value;
+
+const CALLS: &str = include_str!("../fixtures/calls.json");
+const BATCHES: &str = include_str!("../fixtures/batches.json");
+const MESSAGES: &str = include_str!("../fixtures/messages.json");
+
+/// A single call to the conformance service and its expected result.
+#[derive(Clone, Debug, Deserialize)]
+pub struct CallCase<'a> {
+    pub name: &'a str,
+    pub service: &'a str,
+    #[serde(rename = "fn")]
+    pub fn_name: &'a str,
+    pub args: Value,
+    /// Whether the fn is read-only, which lets transports make the call with a read-only request.
+    #[serde(default)]
+    pub read_only: bool,
+    pub expect: Expected,
+}
+
+/// A batch of calls, with the expected result of each one.
+#[derive(Clone, Debug, Deserialize)]
+pub struct BatchCase {
+    pub name: String,
+    pub request: Vec<Value>,
+    pub expect: Vec<Expected>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct MessageCase {
+    pub name: String,
+    pub kind: MessageKind,
+    pub message: Value,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageKind {
+    CallResponseRequest,
+    GenericError,
+    BatchResult,
+    Tunnel,
+    StreamFrame,
+}
+
+/// The expected result of a call, where errors are only matched by their code.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Expected {
+    Data(Value),
+    Error { code: String },
+}
+
+impl Expected {
+    #[allow(clippy::missing_errors_doc)]
+    pub fn check(&self, result: Result<Value, GenericError<'_>>) -> Result<(), String> {
+        match (self, result) {
+            (Self::Data(expected), Ok(data)) if *expected == data => Ok(()),
+            (Self::Error { code }, Err(err)) if *code == err.code => Ok(()),
+            (Self::Data(expected), Ok(data)) => Err(format!("expected {expected}, got {data}")),
+            (Self::Data(expected), Err(err)) => Err(format!("expected {expected}, got {err}")),
+            (Self::Error { code }, Ok(data)) => Err(format!("expected a {code} error, got {data}")),
+            (Self::Error { code }, Err(err)) => Err(format!("expected a {code} error, got {err}")),
+        }
+    }
+}
+
+/// The call fixtures, which are all made to [`ConformanceService`](crate::ConformanceService).
+///
+/// # Panics
+///
+/// Panics if the fixtures are invalid, which is a bug in this crate.
+#[must_use]
+pub fn call_cases() -> Vec<CallCase<'static>> {
+    serde_json::from_str(CALLS).expect("call fixtures should be valid")
+}
+
+/// # Panics
+///
+/// Panics if the fixtures are invalid, which is a bug in this crate.
+#[must_use]
+pub fn batch_cases() -> Vec<BatchCase> {
+    serde_json::from_str(BATCHES).expect("batch fixtures should be valid")
+}
+
+/// Examples of each message in the interface.
+///
+/// # Panics
+///
+/// Panics if the fixtures are invalid, which is a bug in this crate.
+#[must_use]
+pub fn message_cases() -> Vec<MessageCase> {
+    serde_json::from_str(MESSAGES).expect("message fixtures should be valid")
+}
+
+/// A call built from a fixture, which can be made to a fn that doesn't exist.
+pub(crate) struct FixtureCall {
+    pub fn_name: &'static str,
+    pub args: Value,
+    pub read_only: bool,
+}
+
+impl Serialize for FixtureCall {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("fn", self.fn_name)?;
+        map.serialize_entry("args", &self.args)?;
+        map.end()
+    }
+}
+
+impl ServiceRequest for FixtureCall {
+    fn fn_name(&self) -> &'static str {
+        self.fn_name
+    }
+
+    fn read_only(&self) -> bool {
+        self.read_only
+    }
+}
@@ -0,0 +1,244 @@
+#![warn(clippy::pedantic)]
+
+//! Conformance tests for implementations of the interface in `docs/interface.md`.
+//!
+//! The fixtures in the `fixtures` directory are plain JSON, so implementations in other languages
+//! can use them directly. This crate runs them against Rust implementations:
+//!
+//! - [`check_messages`] checks that the message types in netfn match the interface.
+//! - [`check_server`] sends each call to a server, which must serve [`ConformanceService`].
+//! - [`check_transport`] makes each call through a [`Transport`] connected to such a server.
+
+mod fixtures;
+
+use std::fmt;
+
+use netfn::{
+    BatchResult, CallResponseRequest, GenericError, StreamFrame, Transport, TunnelMessage,
+};
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::{Value, json};
+
+pub use fixtures::*;
+
+/// The service that the fixtures are written against.
+#[netfn::service]
+pub trait Conformance {
+    /// Returns the value unchanged.
+    #[netfn(read_only)]
+    async fn echo(&self, value: String) -> String;
+
+    /// Subtracts `b` from `a`, which fails if the args are swapped.
+    async fn subtract(&self, a: i64, b: i64) -> i64;
+
+    async fn nothing(&self);
+
+    /// Returns a `Result`, which is sent as a normal response either way.
+    async fn check(&self, pass: bool) -> Result<u32, String>;
+
+    #[netfn(read_only)]
+    async fn optional(&self, value: Option<String>) -> Option<String>;
+}
+
+/// The reference implementation of [`Conformance`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ConformanceService;
+
+impl Conformance for ConformanceService {
+    async fn echo(&self, value: String) -> String {
+        value
+    }
+
+    async fn subtract(&self, a: i64, b: i64) -> i64 {
+        a - b
+    }
+
+    async fn nothing(&self) {}
+
+    async fn check(&self, pass: bool) -> Result<u32, String> {
+        if pass {
+            Ok(1)
+        } else {
+            Err("check failed".to_owned())
+        }
+    }
+
+    async fn optional(&self, value: Option<String>) -> Option<String> {
+        value
+    }
+}
+
+/// Checks that every message fixture decodes into the matching netfn type, and that it encodes
+/// back into the same JSON.
+#[must_use]
+pub fn check_messages() -> Report {
+    let mut report = Report::default();
+
+    for case in message_cases() {
+        let result = match case.kind {
+            MessageKind::CallResponseRequest => {
+                round_trip::<CallResponseRequest<'_, Value>>(&case.message)
+            }
+            MessageKind::GenericError => round_trip::<GenericError<'_>>(&case.message),
+            MessageKind::BatchResult => round_trip::<BatchResult<'_, Value>>(&case.message),
+            MessageKind::Tunnel => round_trip::<TunnelMessage<'_, Value>>(&case.message),
+            MessageKind::StreamFrame => round_trip::<StreamFrame<'_, Value>>(&case.message),
+        };
+        report.record(&case.name, result);
+    }
+
+    report
+}
+
+fn round_trip<T>(message: &Value) -> Result<(), String>
+where
+    T: Serialize + DeserializeOwned,
+{
+    let decoded: T = serde_json::from_value(message.clone())
+        .map_err(|err| format!("failed to decode: {err}"))?;
+    let encoded =
+        serde_json::to_value(decoded).map_err(|err| format!("failed to encode: {err}"))?;
+
+    if encoded == *message {
+        Ok(())
+    } else {
+        Err(format!("encoded as {encoded} instead of {message}"))
+    }
+}
+
+/// Checks a server against the call and batch fixtures.
+///
+/// `call` sends a request to the server as it would be sent by a call-response transport, which is
+/// either a single call or a batch, and returns its response. Handler errors should be returned as
+/// `Err`.
+pub async fn check_server<F, Fut>(call: F) -> Report
+where
+    F: Fn(Value) -> Fut,
+    Fut: Future<Output = Result<Value, GenericError<'static>>>,
+{
+    let mut report = Report::default();
+
+    for case in call_cases() {
+        let request = json!({
+            "service": case.service,
+            "call": { "fn": case.fn_name, "args": case.args },
+        });
+        let result = call(request).await;
+        report.record(case.name, case.expect.check(result));
+    }
+
+    for case in batch_cases() {
+        let result = match call(Value::Array(case.request)).await {
+            Ok(response) => check_batch(&case.expect, response),
+            Err(err) => Err(format!("failed with {err}")),
+        };
+        report.record(&case.name, result);
+    }
+
+    report
+}
+
+fn check_batch(expect: &[Expected], response: Value) -> Result<(), String> {
+    let results: Vec<BatchResult<'static, Value>> =
+        serde_json::from_value(response).map_err(|err| format!("invalid batch response: {err}"))?;
+    if results.len() != expect.len() {
+        return Err(format!(
+            "expected {} results, but got {}",
+            expect.len(),
+            results.len()
+        ));
+    }
+
+    for (i, (expect, result)) in expect.iter().zip(results).enumerate() {
+        expect
+            .check(result.into())
+            .map_err(|err| format!("result {i}: {err}"))?;
+    }
+    Ok(())
+}
+
+/// Checks a transport connected to a server against the call fixtures.
+///
+/// `handler_error` gets the [`GenericError`] sent by the server out of the transport's errors, and
+/// should return `None` for any other error.
+pub async fn check_transport<T>(
+    transport: &T,
+    handler_error: impl Fn(T::Error) -> Option<GenericError<'static>>,
+) -> Report
+where
+    T: Transport,
+    T::Error: fmt::Display,
+{
+    let mut report = Report::default();
+
+    for case in call_cases() {
+        let call = FixtureCall {
+            fn_name: case.fn_name,
+            args: case.args,
+            read_only: case.read_only,
+        };
+        let result = match transport.call::<_, Value>(case.service, call).await {
+            Ok(response) => case.expect.check(Ok(response)),
+            Err(err) => {
+                let message = err.to_string();
+                match handler_error(err) {
+                    Some(err) => case.expect.check(Err(err)),
+                    None => Err(format!("transport failed: {message}")),
+                }
+            }
+        };
+        report.record(case.name, result);
+    }
+
+    report
+}
+
+/// The results of a conformance check.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub passed: usize,
+    pub failures: Vec<Failure>,
+}
+
+impl Report {
+    fn record(&mut self, case: &str, result: Result<(), String>) {
+        match result {
+            Ok(()) => self.passed += 1,
+            Err(reason) => self.failures.push(Failure {
+                case: case.to_owned(),
+                reason,
+            }),
+        }
+    }
+
+    #[must_use]
+    pub fn is_ok(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    /// Panics with every failure, for use in tests.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any case failed.
+    #[track_caller]
+    pub fn assert_ok(&self) {
+        assert!(self.is_ok(), "{self}");
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} passed, {} failed", self.passed, self.failures.len())?;
+        for failure in &self.failures {
+            write!(f, "\n  {}: {}", failure.case, failure.reason)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Failure {
+    pub case: String,
+    pub reason: String,
+}
@@ -0,0 +1,690 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures::{
+    FutureExt as _, SinkExt as _, Stream, StreamExt as _,
+    channel::{mpsc, oneshot},
+    stream,
+};
+use netfn::{
+    CallResponseRequest, GenericError, TunnelCallError, TunnelMessage, TunnelResponse,
+    server::{Dispatcher, Extensions, StreamService},
+};
+use netfn_conformance::{
+    ConformanceClient, ConformanceExt as _, ConformanceService, Report, check_messages,
+    check_server, check_transport,
+};
+use netfn_transport_http::{
+    ContentEncoding, HttpTransport, Routing, reqwest,
+    reqwest::header::{ACCEPT, ACCEPT_ENCODING, CONTENT_ENCODING},
+    server::HttpServer,
+};
+use netfn_transport_ws::{
+    ListenerEvent, WebSocketCodec, WebSocketListener, WebSocketMessage, WebSocketTransport,
+    server::TunnelServer,
+};
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::{Value, json};
+
+fn dispatcher() -> Dispatcher {
+    Dispatcher::new().with_service(ConformanceService.into_service())
+}
+
+/// Fails the test rather than hanging if a transport never gets a response.
+async fn timeout(check: impl Future<Output = Report>) -> Report {
+    tokio::time::timeout(Duration::from_secs(10), check)
+        .await
+        .expect("the check should finish")
+}
+
+async fn serve_http() -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let router = netfn_transport_http::server::router(dispatcher());
+    tokio::spawn(async move { axum::serve(listener, router).await });
+    format!("http://{addr}/")
+}
+
+#[test]
+fn messages() {
+    check_messages().assert_ok();
+}
+
+#[tokio::test]
+async fn dispatcher_server() {
+    let dispatcher = &dispatcher();
+    let report = check_server(|request| async move {
+        match request {
+            Value::Array(requests) => {
+                let requests = requests
+                    .into_iter()
+                    .map(serde_json::from_value)
+                    .collect::<Result<_, _>>()
+                    .unwrap();
+                Ok(serde_json::to_value(dispatcher.dispatch_batch(requests).await).unwrap())
+            }
+            request => {
+                let request: CallResponseRequest<'_, Value> =
+                    serde_json::from_value(request).unwrap();
+                dispatcher.dispatch(request).await
+            }
+        }
+    })
+    .await;
+    report.assert_ok();
+}
+
+#[tokio::test]
+async fn dispatcher_transport() {
+    timeout(check_transport(&dispatcher(), Some))
+        .await
+        .assert_ok();
+}
+
+#[tokio::test]
+async fn http_server() {
+    let url = serve_http().await;
+    let client = reqwest::Client::new();
+
+    let report = check_server(|request| {
+        let response = client.post(&url).json(&request).send();
+        async move {
+            let response = response.await.unwrap();
+            match response.status().as_u16() {
+                537 => Err(response.json().await.unwrap()),
+                200 => Ok(response.json().await.unwrap()),
+                status => Err(GenericError::new(
+                    "unexpected_status",
+                    format!("status {status}"),
+                )),
+            }
+        }
+    })
+    .await;
+    report.assert_ok();
+}
+
+#[tokio::test]
+async fn http_transport() {
+    let url = serve_http().await;
+    let handler_error = |err| match err {
+        netfn_transport_http::TransportError::Handler(err) => Some(err),
+        _ => None,
+    };
+
+    let transport = HttpTransport::builder().build(url.as_str()).unwrap();
+    timeout(check_transport(&transport, handler_error))
+        .await
+        .assert_ok();
+
+    let transport = HttpTransport::builder()
+        .get_read_only(true)
+        .build(url.as_str())
+        .unwrap();
+    timeout(check_transport(&transport, handler_error))
+        .await
+        .assert_ok();
+
+    let transport = HttpTransport::builder()
+        .routing(Routing::Path)
+        .build(url.as_str())
+        .unwrap();
+    timeout(check_transport(&transport, handler_error))
+        .await
+        .assert_ok();
+}
+
+/// The `Content-Encoding` of a request and of its response.
+type EncodingPair = (Option<String>, Option<String>);
+
+/// The encodings of each request to a server.
+#[derive(Clone, Default)]
+struct Encodings(Arc<Mutex<Vec<EncodingPair>>>);
+
+impl Encodings {
+    fn take(&self) -> Vec<EncodingPair> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+/// Serves the router behind a layer that records the encodings of each request, as they arrive
+/// and as the response leaves.
+async fn serve_http_recording(server: HttpServer, encodings: Encodings) -> String {
+    async fn record(
+        axum::extract::State(encodings): axum::extract::State<Encodings>,
+        request: axum::extract::Request,
+        next: axum::middleware::Next,
+    ) -> axum::response::Response {
+        let encoding = |headers: &axum::http::HeaderMap| {
+            headers
+                .get(CONTENT_ENCODING)
+                .map(|value| value.to_str().unwrap().to_owned())
+        };
+        let request_encoding = encoding(request.headers());
+        let response = next.run(request).await;
+        let response_encoding = encoding(response.headers());
+        encodings
+            .0
+            .lock()
+            .unwrap()
+            .push((request_encoding, response_encoding));
+        response
+    }
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let router = server
+        .into_router()
+        .layer(axum::middleware::from_fn_with_state(encodings, record));
+    tokio::spawn(async move { axum::serve(listener, router).await });
+    format!("http://{addr}/")
+}
+
+#[tokio::test]
+async fn http_compression() {
+    for encoding in [
+        ContentEncoding::Brotli,
+        ContentEncoding::Gzip,
+        ContentEncoding::Zstd,
+    ] {
+        let encodings = Encodings::default();
+        let url = serve_http_recording(HttpServer::new(dispatcher()), encodings.clone()).await;
+        let client = ConformanceClient::new(
+            HttpTransport::builder()
+                .compress_requests(encoding, 128)
+                .header(
+                    ACCEPT_ENCODING,
+                    reqwest::header::HeaderValue::from_static(encoding.name()),
+                )
+                .build(url.as_str())
+                .unwrap(),
+        );
+
+        // Small requests and responses are sent as they are, while larger ones are compressed
+        // on the way and decompressed at the other end
+        assert_eq!(client.echo("short".to_owned()).await.unwrap(), "short");
+        let long = "long".repeat(64);
+        assert_eq!(client.echo(long.clone()).await.unwrap(), long);
+
+        let name = Some(encoding.name().to_owned());
+        assert_eq!(encodings.take(), [(None, None), (name.clone(), name)]);
+    }
+}
+
+#[tokio::test]
+async fn http_ndjson_streams_are_not_compressed() {
+    let encodings = Encodings::default();
+    let server = HttpServer::new(dispatcher().with_stream_service(Forever));
+    let url = serve_http_recording(server, encodings.clone()).await;
+
+    let response = reqwest::Client::new()
+        .post(&url)
+        .header(ACCEPT, "application/x-ndjson")
+        .header(ACCEPT_ENCODING, "gzip")
+        .json(&json!({ "service": "Forever", "call": { "fn": "Forever", "args": {} } }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(
+        response.headers()[reqwest::header::CONTENT_TYPE],
+        "application/x-ndjson"
+    );
+    assert_eq!(encodings.take(), [(None, None)]);
+}
+
+#[derive(Clone, Copy)]
+struct JsonCodec;
+
+impl WebSocketCodec for JsonCodec {
+    type EncodeError = serde_json::Error;
+    type DecodeError = serde_json::Error;
+
+    fn encode<T>(&self, value: &T) -> Result<WebSocketMessage, Self::EncodeError>
+    where
+        T: Serialize,
+    {
+        serde_json::to_string(value).map(WebSocketMessage::Json)
+    }
+
+    fn decode<T>(&self, message: &WebSocketMessage) -> Result<T, Self::DecodeError>
+    where
+        T: DeserializeOwned,
+    {
+        match message {
+            WebSocketMessage::Json(json) => serde_json::from_str(json),
+            WebSocketMessage::MessagePack(_) => Err(serde::de::Error::custom(
+                "MessagePack messages are not supported",
+            )),
+        }
+    }
+}
+
+/// Serves calls over an in-memory tunnel, following the interface rather than any transport.
+async fn serve_tunnel(
+    mut incoming: mpsc::UnboundedReceiver<WebSocketMessage>,
+    outgoing: mpsc::UnboundedSender<WebSocketMessage>,
+) {
+    let dispatcher = dispatcher();
+    while let Some(WebSocketMessage::Json(json)) = incoming.next().await {
+        let Ok(TunnelMessage::Request(request)) = serde_json::from_str(&json) else {
+            panic!("expected a tunnel request, got {json}");
+        };
+
+        let msg_ref = request.msg_ref;
+        let reply = match dispatcher.dispatch(request.payload).await {
+            Ok(data) => TunnelMessage::Response(TunnelResponse { msg_ref, data }),
+            Err(error) => TunnelMessage::Error(TunnelCallError { msg_ref, error }),
+        };
+        let reply = serde_json::to_string(&reply).unwrap();
+        outgoing
+            .unbounded_send(WebSocketMessage::Json(reply))
+            .unwrap();
+    }
+}
+
+#[tokio::test]
+async fn ws_transport() {
+    let (client_tx, server_rx) = mpsc::unbounded();
+    let (server_tx, mut client_rx) = mpsc::unbounded();
+    tokio::spawn(serve_tunnel(server_rx, server_tx));
+
+    let (transport, mut listener) = WebSocketTransport::new(JsonCodec, 16);
+    tokio::spawn(async move {
+        let mut sink = client_tx.sink_map_err(|_| ());
+        listener.listen(&mut sink, &mut client_rx).await.unwrap();
+    });
+
+    let handler_error = |err| match err {
+        netfn_transport_ws::TransportError::Handler(err) => Some(err),
+        _ => None,
+    };
+    timeout(check_transport(&transport, handler_error))
+        .await
+        .assert_ok();
+}
+
+#[tokio::test]
+async fn ws_server() {
+    let (client_tx, mut server_rx) = mpsc::unbounded();
+    let (server_tx, mut client_rx) = mpsc::unbounded();
+    tokio::spawn(async move {
+        let mut sink = server_tx.sink_map_err(|_| ());
+        TunnelServer::new(dispatcher(), JsonCodec)
+            .serve_tunnel(Extensions::new(), &mut sink, &mut server_rx)
+            .await
+            .unwrap();
+    });
+
+    let (transport, mut listener) = WebSocketTransport::new(JsonCodec, 16);
+    tokio::spawn(async move {
+        let mut sink = client_tx.sink_map_err(|_| ());
+        listener.listen(&mut sink, &mut client_rx).await.unwrap();
+    });
+
+    let handler_error = |err| match err {
+        netfn_transport_ws::TransportError::Handler(err) => Some(err),
+        _ => None,
+    };
+    timeout(check_transport(&transport, handler_error))
+        .await
+        .assert_ok();
+}
+
+/// A stream that stays open until it is closed, which holds a slot while it is open.
+struct Forever;
+
+impl StreamService for Forever {
+    const NAME: &'static str = "Forever";
+    type Request = Value;
+    type Item = ();
+
+    fn open(
+        &self,
+        _: Self::Request,
+    ) -> impl Stream<Item = Result<Self::Item, GenericError<'static>>> + Send + 'static {
+        stream::pending()
+    }
+}
+
+type WsError = netfn_transport_ws::TransportError<serde_json::Error, serde_json::Error, ()>;
+
+/// A tunnel to the server that messages are sent over one at a time.
+struct RawTunnel {
+    tx: mpsc::UnboundedSender<WebSocketMessage>,
+    rx: mpsc::UnboundedReceiver<WebSocketMessage>,
+    served: tokio::task::JoinHandle<Result<(), WsError>>,
+}
+
+impl RawTunnel {
+    fn open(server: Arc<TunnelServer<JsonCodec>>) -> Self {
+        let (tx, mut server_rx) = mpsc::unbounded();
+        let (server_tx, rx) = mpsc::unbounded();
+        let served = tokio::spawn(async move {
+            let mut sink = server_tx.sink_map_err(|_| ());
+            server
+                .serve_tunnel(Extensions::new(), &mut sink, &mut server_rx)
+                .await
+        });
+        Self { tx, rx, served }
+    }
+
+    async fn send(&mut self, message: Value) -> Value {
+        self.tx
+            .unbounded_send(WebSocketMessage::Json(message.to_string()))
+            .unwrap();
+        let reply = tokio::time::timeout(Duration::from_secs(10), self.rx.next())
+            .await
+            .expect("the server should reply")
+            .expect("the tunnel should be open");
+        let WebSocketMessage::Json(reply) = reply else {
+            panic!("expected a JSON reply");
+        };
+        serde_json::from_str(&reply).unwrap()
+    }
+}
+
+fn echo(msg_ref: u64) -> Value {
+    json!({
+        "type": "request",
+        "ref": msg_ref,
+        "service": "Conformance",
+        "call": { "fn": "Echo", "args": { "0": "hi" } },
+    })
+}
+
+#[tokio::test]
+async fn ws_server_limits_each_tunnel() {
+    let dispatcher = dispatcher()
+        .with_stream_service(Forever)
+        .with_max_concurrency(4)
+        .with_max_in_flight_per_connection(1);
+    let server = Arc::new(TunnelServer::new(dispatcher, JsonCodec));
+    let mut first = RawTunnel::open(server.clone());
+    let mut second = RawTunnel::open(server);
+
+    let open = json!({
+        "type": "stream_open",
+        "ref": 0,
+        "service": "Forever",
+        "call": { "fn": "Forever", "args": {} },
+    });
+    let reply = first.send(open).await;
+    assert_eq!(reply["type"], "stream_ready");
+    let handle = reply["handle"].clone();
+
+    // The open stream takes the first tunnel's only slot, but not the second's
+    let reply = first.send(echo(1)).await;
+    assert_eq!(reply["type"], "error");
+    assert_eq!(reply["error"]["code"], "overloaded");
+
+    let reply = second.send(echo(0)).await;
+    assert_eq!(reply, json!({ "type": "response", "ref": 0, "data": "hi" }));
+
+    // Closing the stream gives the slot back
+    first
+        .tx
+        .unbounded_send(WebSocketMessage::Json(
+            json!({ "type": "stream_close", "handle": handle }).to_string(),
+        ))
+        .unwrap();
+    let mut reply = first.send(echo(2)).await;
+    for msg_ref in 3..100 {
+        // The close may not have been handled before the call was dispatched
+        if reply["type"] == "response" {
+            break;
+        }
+        tokio::task::yield_now().await;
+        reply = first.send(echo(msg_ref)).await;
+    }
+    assert_eq!(reply["type"], "response");
+}
+
+/// The server end of a tunnel to a client, which answers calls by hand.
+struct FakeServer {
+    tx: mpsc::UnboundedSender<WebSocketMessage>,
+    rx: mpsc::UnboundedReceiver<WebSocketMessage>,
+}
+
+impl FakeServer {
+    async fn next(&mut self) -> Option<Value> {
+        let message = tokio::time::timeout(Duration::from_millis(100), self.rx.next())
+            .await
+            .ok()??;
+        let WebSocketMessage::Json(message) = message else {
+            panic!("expected a JSON message");
+        };
+        Some(serde_json::from_str(&message).unwrap())
+    }
+
+    fn reply(&self, request: &Value) {
+        let reply = json!({
+            "type": "response",
+            "ref": request["ref"],
+            "data": request["call"]["args"]["0"],
+        });
+        self.tx
+            .unbounded_send(WebSocketMessage::Json(reply.to_string()))
+            .unwrap();
+    }
+}
+
+type ClientEnd = (
+    mpsc::UnboundedSender<WebSocketMessage>,
+    mpsc::UnboundedReceiver<WebSocketMessage>,
+);
+
+fn fake_tunnel() -> (FakeServer, ClientEnd) {
+    let (client_tx, rx) = mpsc::unbounded();
+    let (tx, client_rx) = mpsc::unbounded();
+    (FakeServer { tx, rx }, (client_tx, client_rx))
+}
+
+/// Runs the listener over each tunnel sent to it in turn, forwarding its events.
+fn run_listener(
+    listener: WebSocketListener<JsonCodec, ()>,
+) -> (
+    mpsc::UnboundedSender<ClientEnd>,
+    mpsc::UnboundedReceiver<ListenerEvent>,
+) {
+    let (events_tx, events) = mpsc::unbounded();
+    let mut listener = listener.on_event(move |event| {
+        let _ = events_tx.unbounded_send(event);
+    });
+
+    let (tunnels_tx, mut tunnels) = mpsc::unbounded::<ClientEnd>();
+    tokio::spawn(async move {
+        while let Some((client_tx, mut client_rx)) = tunnels.next().await {
+            let mut sink = client_tx.sink_map_err(|_| ());
+            listener.listen(&mut sink, &mut client_rx).await.unwrap();
+        }
+    });
+    (tunnels_tx, events)
+}
+
+async fn next_event(events: &mut mpsc::UnboundedReceiver<ListenerEvent>) -> ListenerEvent {
+    tokio::time::timeout(Duration::from_secs(10), events.next())
+        .await
+        .expect("the listener should send an event")
+        .unwrap()
+}
+
+#[tokio::test]
+async fn ws_listener_drains_sent_calls() {
+    let (transport, listener) = WebSocketTransport::new(JsonCodec, 16);
+    let mut closer = listener.closer();
+    let (tunnels, mut events) = run_listener(listener);
+    let (mut server, client) = fake_tunnel();
+    tunnels.unbounded_send(client).unwrap();
+
+    let sent = tokio::spawn({
+        let client = ConformanceClient::new(transport.clone());
+        async move { client.echo("sent".to_owned()).await }
+    });
+    let request = server.next().await.expect("the call should be sent");
+
+    let (_deadline_tx, deadline) = oneshot::channel::<()>();
+    closer.drain(deadline.map(|_| ()));
+    assert_eq!(
+        next_event(&mut events).await,
+        ListenerEvent::Draining { in_flight: 1 },
+    );
+
+    // New calls aren't sent while draining, and wait for the next tunnel instead
+    let waiting = tokio::spawn({
+        let client = ConformanceClient::new(transport);
+        async move { client.echo("waiting".to_owned()).await }
+    });
+    assert_eq!(server.next().await, None);
+
+    server.reply(&request);
+    assert_eq!(sent.await.unwrap().unwrap(), "sent");
+    assert_eq!(
+        next_event(&mut events).await,
+        ListenerEvent::Stopped { disconnected: 0 },
+    );
+
+    let (mut server, client) = fake_tunnel();
+    tunnels.unbounded_send(client).unwrap();
+    let request = server
+        .next()
+        .await
+        .expect("the waiting call should be sent");
+    server.reply(&request);
+    assert_eq!(waiting.await.unwrap().unwrap(), "waiting");
+}
+
+#[tokio::test]
+async fn ws_listener_drain_deadline_closes() {
+    let (transport, listener) = WebSocketTransport::new(JsonCodec, 16);
+    let mut closer = listener.closer();
+    let (tunnels, mut events) = run_listener(listener);
+    let (mut server, client) = fake_tunnel();
+    tunnels.unbounded_send(client).unwrap();
+
+    let client = ConformanceClient::new(transport);
+    let sent = tokio::spawn(async move { client.echo("sent".to_owned()).await });
+    server.next().await.expect("the call should be sent");
+
+    let (deadline_tx, deadline) = oneshot::channel::<()>();
+    closer.drain(deadline.map(|_| ()));
+    assert_eq!(
+        next_event(&mut events).await,
+        ListenerEvent::Draining { in_flight: 1 },
+    );
+
+    // The call is never answered, so the deadline stops the listener
+    deadline_tx.send(()).unwrap();
+    assert_eq!(
+        next_event(&mut events).await,
+        ListenerEvent::Stopped { disconnected: 1 },
+    );
+    assert!(matches!(
+        sent.await.unwrap(),
+        Err(netfn_transport_ws::TransportError::Disconnected),
+    ));
+}
+
+#[tokio::test]
+async fn ws_server_closes_on_large_messages() {
+    let server = Arc::new(TunnelServer::new(dispatcher(), JsonCodec).max_message_size(256));
+    let mut tunnel = RawTunnel::open(server);
+
+    let reply = tunnel.send(echo(0)).await;
+    assert_eq!(reply["type"], "response");
+
+    let mut large = echo(1);
+    large["call"]["args"]["0"] = Value::String("x".repeat(256));
+    tunnel
+        .tx
+        .unbounded_send(WebSocketMessage::Json(large.to_string()))
+        .unwrap();
+    let served = tokio::time::timeout(Duration::from_secs(10), tunnel.served)
+        .await
+        .expect("the tunnel should close")
+        .unwrap();
+    assert!(matches!(
+        served,
+        Err(netfn_transport_ws::TransportError::TooLarge { limit: 256 }),
+    ));
+    assert!(tunnel.rx.next().await.is_none());
+}
+
+#[tokio::test]
+async fn ws_listener_closes_on_large_messages() {
+    let (transport, listener) = WebSocketTransport::new(JsonCodec, 16);
+    let (tunnels, mut events) = run_listener(listener.max_message_size(256));
+    let (mut server, client) = fake_tunnel();
+    tunnels.unbounded_send(client).unwrap();
+
+    let client = ConformanceClient::new(transport);
+    let sent = tokio::spawn(async move { client.echo("x".repeat(256)).await });
+    let request = server.next().await.expect("the call should be sent");
+    server.reply(&request);
+
+    assert!(matches!(
+        next_event(&mut events).await,
+        ListenerEvent::TooLarge { limit: 256, .. },
+    ));
+    assert_eq!(
+        next_event(&mut events).await,
+        ListenerEvent::Stopped { disconnected: 0 },
+    );
+    assert!(matches!(
+        sent.await.unwrap(),
+        Err(netfn_transport_ws::TransportError::TooLarge { limit: 256 }),
+    ));
+}
+
+/// Serves a fixed response body, either with its length or in chunks without one.
+async fn serve_http_body(body: &'static str) -> String {
+    let chunks = || {
+        stream::iter(
+            body.as_bytes()
+                .chunks(16)
+                .map(|chunk| Ok::<_, std::convert::Infallible>(chunk.to_vec())),
+        )
+    };
+    let router = axum::Router::new()
+        .route("/sized/", axum::routing::post(move || async move { body }))
+        .route(
+            "/chunked/",
+            axum::routing::post(move || async move { axum::body::Body::from_stream(chunks()) }),
+        );
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, router).await });
+    format!("http://{addr}/")
+}
+
+#[tokio::test]
+async fn http_transport_limits_response_sizes() {
+    let body = r#""a response that is longer than the limit""#;
+    let url = serve_http_body(body).await;
+
+    for path in ["sized", "chunked"] {
+        let url = format!("{url}{path}/");
+        let client = |limit| {
+            ConformanceClient::new(
+                HttpTransport::builder()
+                    .max_response_size(limit)
+                    .build(url.as_str())
+                    .unwrap(),
+            )
+        };
+
+        let result = client(16).echo(String::new()).await;
+        assert!(
+            matches!(
+                result,
+                Err(netfn_transport_http::TransportError::TooLarge { limit: 16 }),
+            ),
+            "{path}: {result:?}",
+        );
+
+        let result = client(body.len()).echo(String::new()).await;
+        assert_eq!(result.unwrap(), body.trim_matches('"'), "{path}");
+    }
+}
@@ -0,0 +1,477 @@
+use std::{borrow::Cow, fmt};
+
+use serde::{Deserialize, Serialize};
+
+/// A description of a service and its fns, generated alongside the service by the macro.
+///
+/// Types are given as they were written in the service trait, so they describe the Rust side of
+/// the service rather than the wire format.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServiceDescriptor<'a> {
+    /// The name of the service as it appears on the wire, which includes the version if it
+    /// isn't 1, such as `UserApi.v2`.
+    pub name: Cow<'a, str>,
+    #[serde(default = "default_version")]
+    pub version: u32,
+    #[serde(default, skip_serializing_if = "str::is_empty")]
+    pub docs: Cow<'a, str>,
+    pub fns: Cow<'a, [FnDescriptor<'a>]>,
+}
+
+fn default_version() -> u32 {
+    1
+}
+
+impl<'a> ServiceDescriptor<'a> {
+    /// Finds a fn by its wire name.
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<&FnDescriptor<'a>> {
+        self.fns.iter().find(|tfn| tfn.name == name)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FnDescriptor<'a> {
+    /// The name of the fn as it appears on the wire.
+    pub name: Cow<'a, str>,
+    #[serde(default, skip_serializing_if = "str::is_empty")]
+    pub docs: Cow<'a, str>,
+    /// The args in the order they are sent, with each one keyed by its index.
+    pub args: Cow<'a, [ArgDescriptor<'a>]>,
+    /// The return type, which is `()` for fns that don't return anything.
+    pub returns: Cow<'a, str>,
+    #[serde(default)]
+    pub read_only: bool,
+    /// The permission that callers need, set with `#[netfn(requires = "...")]`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requires: Option<Cow<'a, str>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArgDescriptor<'a> {
+    pub name: Cow<'a, str>,
+    #[serde(rename = "type")]
+    pub ty: Cow<'a, str>,
+}
+
+impl ServiceDescriptor<'_> {
+    /// Compares this descriptor with a newer one, listing the changes that would break clients
+    /// built against this one.
+    ///
+    /// Types are compared by how they are written, so a type that is renamed or written with a
+    /// different path is reported as changed even if it is encoded in the same way.
+    #[must_use]
+    pub fn breaking_changes(&self, new: &ServiceDescriptor<'_>) -> Vec<BreakingChange> {
+        let mut changes = Vec::new();
+        if self.name != new.name {
+            changes.push(BreakingChange::ServiceRenamed {
+                old: self.name.to_string(),
+                new: new.name.to_string(),
+            });
+        }
+
+        for old_fn in &*self.fns {
+            match new.get(&old_fn.name) {
+                Some(new_fn) => old_fn.breaking_changes(new_fn, &mut changes),
+                None => changes.push(BreakingChange::FnRemoved {
+                    name: old_fn.name.to_string(),
+                }),
+            }
+        }
+
+        changes
+    }
+}
+
+impl FnDescriptor<'_> {
+    fn breaking_changes(&self, new: &FnDescriptor<'_>, changes: &mut Vec<BreakingChange>) {
+        let tfn = || self.name.to_string();
+
+        for (index, old_arg) in self.args.iter().enumerate() {
+            let Some(new_arg) = new.args.get(index) else {
+                changes.push(BreakingChange::ArgRemoved {
+                    tfn: tfn(),
+                    index,
+                    name: old_arg.name.to_string(),
+                });
+                continue;
+            };
+
+            // Args are sent by position, so names only matter when they show an arg has moved.
+            if old_arg.name != new_arg.name
+                && let Some(to) = new.args.iter().position(|arg| arg.name == old_arg.name)
+            {
+                changes.push(BreakingChange::ArgMoved {
+                    tfn: tfn(),
+                    name: old_arg.name.to_string(),
+                    from: index,
+                    to,
+                });
+            } else if old_arg.ty != new_arg.ty {
+                changes.push(BreakingChange::ArgTypeChanged {
+                    tfn: tfn(),
+                    index,
+                    name: new_arg.name.to_string(),
+                    old: old_arg.ty.to_string(),
+                    new: new_arg.ty.to_string(),
+                });
+            }
+        }
+
+        // Missing `Option` args are decoded as `None`, so only other args break old clients.
+        for (index, new_arg) in new.args.iter().enumerate().skip(self.args.len()) {
+            if !is_option(&new_arg.ty) {
+                changes.push(BreakingChange::ArgAdded {
+                    tfn: tfn(),
+                    index,
+                    name: new_arg.name.to_string(),
+                });
+            }
+        }
+
+        if self.returns != new.returns {
+            changes.push(BreakingChange::ReturnTypeChanged {
+                tfn: tfn(),
+                old: self.returns.to_string(),
+                new: new.returns.to_string(),
+            });
+        }
+        if self.read_only && !new.read_only {
+            changes.push(BreakingChange::ReadOnlyRemoved { tfn: tfn() });
+        }
+    }
+}
+
+fn is_option(ty: &str) -> bool {
+    let ty = ty.trim_start_matches("::");
+    let ty = ["std::option::", "core::option::"]
+        .iter()
+        .find_map(|path| ty.strip_prefix(path))
+        .unwrap_or(ty);
+    ty.starts_with("Option<")
+}
+
+/// A change between two versions of a service that breaks clients built against the old one.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum BreakingChange {
+    /// The service has a different name on the wire, which includes changing its version.
+    ServiceRenamed {
+        old: String,
+        new: String,
+    },
+    FnRemoved {
+        name: String,
+    },
+    /// An arg was removed, so the value sent for it is ignored.
+    ArgRemoved {
+        #[serde(rename = "fn")]
+        tfn: String,
+        index: usize,
+        name: String,
+    },
+    /// An arg was added that old clients don't send.
+    ArgAdded {
+        #[serde(rename = "fn")]
+        tfn: String,
+        index: usize,
+        name: String,
+    },
+    /// An arg has moved to a different position, so old clients send it in the wrong place.
+    ArgMoved {
+        #[serde(rename = "fn")]
+        tfn: String,
+        name: String,
+        from: usize,
+        to: usize,
+    },
+    ArgTypeChanged {
+        #[serde(rename = "fn")]
+        tfn: String,
+        index: usize,
+        name: String,
+        old: String,
+        new: String,
+    },
+    ReturnTypeChanged {
+        #[serde(rename = "fn")]
+        tfn: String,
+        old: String,
+        new: String,
+    },
+    /// The fn is no longer read-only, so it can't be called through read-only requests.
+    ReadOnlyRemoved {
+        #[serde(rename = "fn")]
+        tfn: String,
+    },
+}
+
+impl fmt::Display for BreakingChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ServiceRenamed { old, new } => write!(f, "service {old} was renamed to {new}"),
+            Self::FnRemoved { name } => write!(f, "fn {name} was removed"),
+            Self::ArgRemoved { tfn, index, name } => {
+                write!(f, "fn {tfn}: arg {index} ({name}) was removed")
+            }
+            Self::ArgAdded { tfn, index, name } => {
+                write!(f, "fn {tfn}: required arg {index} ({name}) was added")
+            }
+            Self::ArgMoved {
+                tfn,
+                name,
+                from,
+                to,
+            } => {
+                write!(f, "fn {tfn}: arg {name} moved from {from} to {to}")
+            }
+            Self::ArgTypeChanged {
+                tfn,
+                index,
+                name,
+                old,
+                new,
+            } => write!(
+                f,
+                "fn {tfn}: arg {index} ({name}) changed type from {old} to {new}"
+            ),
+            Self::ReturnTypeChanged { tfn, old, new } => {
+                write!(f, "fn {tfn}: return type changed from {old} to {new}")
+            }
+            Self::ReadOnlyRemoved { tfn } => write!(f, "fn {tfn} is no longer read-only"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(name: &'static str, fns: Vec<FnDescriptor<'static>>) -> ServiceDescriptor<'static> {
+        ServiceDescriptor {
+            name: name.into(),
+            version: 1,
+            docs: "".into(),
+            fns: fns.into(),
+        }
+    }
+
+    fn tfn(
+        name: &'static str,
+        args: &[(&'static str, &'static str)],
+        returns: &'static str,
+    ) -> FnDescriptor<'static> {
+        FnDescriptor {
+            name: name.into(),
+            docs: "".into(),
+            args: args
+                .iter()
+                .map(|(name, ty)| ArgDescriptor {
+                    name: (*name).into(),
+                    ty: (*ty).into(),
+                })
+                .collect::<Vec<_>>()
+                .into(),
+            returns: returns.into(),
+            read_only: false,
+            requires: None,
+        }
+    }
+
+    fn get_user() -> FnDescriptor<'static> {
+        tfn("GetUser", &[("id", "u64"), ("full", "bool")], "User")
+    }
+
+    #[test]
+    fn unchanged() {
+        let old = service("Users", vec![get_user(), tfn("Ping", &[], "()")]);
+        assert_eq!(old.breaking_changes(&old.clone()), []);
+    }
+
+    #[test]
+    fn docs_and_new_fns_are_not_breaking() {
+        let old = service("Users", vec![get_user()]);
+        let mut new = service("Users", vec![get_user(), tfn("Ping", &[], "()")]);
+        new.docs = "Manages users.".into();
+        assert_eq!(old.breaking_changes(&new), []);
+    }
+
+    #[test]
+    fn fn_removed() {
+        let old = service("Users", vec![get_user(), tfn("Ping", &[], "()")]);
+        let new = service("Users", vec![get_user()]);
+        assert_eq!(
+            old.breaking_changes(&new),
+            [BreakingChange::FnRemoved {
+                name: "Ping".to_owned()
+            }]
+        );
+    }
+
+    #[test]
+    fn arg_removed() {
+        let old = service("Users", vec![get_user()]);
+        let new = service("Users", vec![tfn("GetUser", &[("id", "u64")], "User")]);
+        assert_eq!(
+            old.breaking_changes(&new),
+            [BreakingChange::ArgRemoved {
+                tfn: "GetUser".to_owned(),
+                index: 1,
+                name: "full".to_owned(),
+            }]
+        );
+    }
+
+    #[test]
+    fn arg_added() {
+        let old = service("Users", vec![get_user()]);
+        let new = service(
+            "Users",
+            vec![tfn(
+                "GetUser",
+                &[("id", "u64"), ("full", "bool"), ("tenant", "String")],
+                "User",
+            )],
+        );
+        assert_eq!(
+            old.breaking_changes(&new),
+            [BreakingChange::ArgAdded {
+                tfn: "GetUser".to_owned(),
+                index: 2,
+                name: "tenant".to_owned(),
+            }]
+        );
+    }
+
+    #[test]
+    fn optional_arg_added() {
+        let old = service("Users", vec![get_user()]);
+        let new = service(
+            "Users",
+            vec![tfn(
+                "GetUser",
+                &[
+                    ("id", "u64"),
+                    ("full", "bool"),
+                    ("tenant", "Option<String>"),
+                    ("fields", "std::option::Option<Vec<String>>"),
+                ],
+                "User",
+            )],
+        );
+        assert_eq!(old.breaking_changes(&new), []);
+    }
+
+    #[test]
+    fn arg_type_changed() {
+        let old = service("Users", vec![get_user()]);
+        let new = service(
+            "Users",
+            vec![tfn(
+                "GetUser",
+                &[("id", "String"), ("full", "bool")],
+                "User",
+            )],
+        );
+        assert_eq!(
+            old.breaking_changes(&new),
+            [BreakingChange::ArgTypeChanged {
+                tfn: "GetUser".to_owned(),
+                index: 0,
+                name: "id".to_owned(),
+                old: "u64".to_owned(),
+                new: "String".to_owned(),
+            }]
+        );
+    }
+
+    #[test]
+    fn arg_moved() {
+        let old = service("Users", vec![get_user()]);
+        let new = service(
+            "Users",
+            vec![tfn("GetUser", &[("full", "bool"), ("id", "u64")], "User")],
+        );
+        assert_eq!(
+            old.breaking_changes(&new),
+            [
+                BreakingChange::ArgMoved {
+                    tfn: "GetUser".to_owned(),
+                    name: "id".to_owned(),
+                    from: 0,
+                    to: 1,
+                },
+                BreakingChange::ArgMoved {
+                    tfn: "GetUser".to_owned(),
+                    name: "full".to_owned(),
+                    from: 1,
+                    to: 0,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn arg_renamed_is_not_breaking() {
+        let old = service("Users", vec![get_user()]);
+        let new = service(
+            "Users",
+            vec![tfn(
+                "GetUser",
+                &[("user_id", "u64"), ("full", "bool")],
+                "User",
+            )],
+        );
+        assert_eq!(old.breaking_changes(&new), []);
+    }
+
+    #[test]
+    fn return_type_changed() {
+        let old = service("Users", vec![get_user()]);
+        let new = service(
+            "Users",
+            vec![tfn(
+                "GetUser",
+                &[("id", "u64"), ("full", "bool")],
+                "Option<User>",
+            )],
+        );
+        assert_eq!(
+            old.breaking_changes(&new),
+            [BreakingChange::ReturnTypeChanged {
+                tfn: "GetUser".to_owned(),
+                old: "User".to_owned(),
+                new: "Option<User>".to_owned(),
+            }]
+        );
+    }
+
+    #[test]
+    fn read_only_removed() {
+        let mut read_only = get_user();
+        read_only.read_only = true;
+        let old = service("Users", vec![read_only.clone()]);
+        let new = service("Users", vec![get_user()]);
+        assert_eq!(
+            old.breaking_changes(&new),
+            [BreakingChange::ReadOnlyRemoved {
+                tfn: "GetUser".to_owned()
+            }]
+        );
+        assert_eq!(new.breaking_changes(&old), []);
+    }
+
+    #[test]
+    fn version_bump() {
+        let old = service("Users", vec![get_user()]);
+        let mut new = service("Users.v2", vec![get_user()]);
+        new.version = 2;
+        assert_eq!(
+            old.breaking_changes(&new),
+            [BreakingChange::ServiceRenamed {
+                old: "Users".to_owned(),
+                new: "Users.v2".to_owned(),
+            }]
+        );
+    }
+}
@@ -1,13 +1,39 @@
 #![warn(clippy::pedantic)]
 
+mod descriptor;
+#[cfg(feature = "server")]
+pub mod limit;
+pub mod metrics;
+#[cfg(feature = "mock")]
+pub mod mock;
+pub mod reflection;
+#[cfg(feature = "schema")]
+pub mod schema;
+#[cfg(feature = "server")]
+pub mod server;
+
 use std::{borrow::Cow, error::Error, fmt::Display};
 
+pub use descriptor::*;
+#[cfg(feature = "schema")]
+#[doc(hidden)]
+pub use schemars;
 #[doc(hidden)]
 pub use serde;
 use serde::{Deserialize, Serialize};
 
 pub trait Service {
     const NAME: &'static str;
+    /// Describes the service for reflection, which the macro fills in.
+    ///
+    /// Services written by hand default to a descriptor without any fns, which dispatchers treat
+    /// as accepting calls to any fn rather than none.
+    const DESCRIPTOR: ServiceDescriptor<'static> = ServiceDescriptor {
+        name: Cow::Borrowed(Self::NAME),
+        version: 1,
+        docs: Cow::Borrowed(""),
+        fns: Cow::Borrowed(&[]),
+    };
     type Request;
     type Response;
 
@@ -17,6 +43,48 @@ pub trait Service {
     ) -> impl Future<Output = Self::Response> + compat::NetfnSend;
 }
 
+/// Information about the fn that a request is calling.
+///
+/// This is implemented by the generated request enums so that transports and servers can
+/// inspect a call without having to understand its args.
+pub trait ServiceRequest {
+    /// The name of the fn as it appears on the wire.
+    fn fn_name(&self) -> &'static str;
+
+    /// Whether the fn has been marked with `#[netfn(read_only)]`.
+    fn read_only(&self) -> bool;
+
+    /// The permission needed to call the fn, set with `#[netfn(requires = "...")]` on the fn or
+    /// the service.
+    fn requires(&self) -> Option<&'static str> {
+        None
+    }
+}
+
+/// Decodes the response to a request.
+///
+/// Responses don't say which fn they came from, and several fns may return the same type, so the
+/// request is needed to know what a response holds. This is implemented by the generated request
+/// enums for anything that decodes responses outside of a client, such as proxies and loggers.
+pub trait DecodeResponse: ServiceRequest {
+    type Response;
+
+    #[allow(clippy::missing_errors_doc)]
+    fn decode_response<'de, D>(&self, deserializer: D) -> Result<Self::Response, D::Error>
+    where
+        D: serde::Deserializer<'de>;
+}
+
+/// Sends calls to a service, which the generated clients are built on.
+///
+/// `request` is one of the generated request enums, which serializes as the `call` of a
+/// [`CallResponseRequest`] to `service`. It also implements [`ServiceRequest`], so transports
+/// can look at the fn being called without serializing it first, such as to send read-only fns
+/// as `GET` requests. Transports that don't need it only have to repeat the bound.
+///
+/// The response is decoded as `Res`, while errors sent back by the handler should be returned in
+/// a way that [`HandlerError`] can find, so that they can be told apart from the transport
+/// failing.
 pub trait Transport {
     type Error;
 
@@ -26,10 +94,22 @@ pub trait Transport {
         request: Req,
     ) -> impl Future<Output = Result<Res, Self::Error>> + compat::NetfnSend
     where
-        Req: compat::NetfnSend + Serialize,
+        Req: compat::NetfnSend + Serialize + ServiceRequest,
         Res: compat::NetfnSend + serde::de::DeserializeOwned;
 }
 
+/// Transport errors that can hold a [`GenericError`] sent back by the handler, which lets
+/// wrappers around any transport tell handler errors apart from the transport failing.
+pub trait HandlerError {
+    fn handler_error(&self) -> Option<&GenericError<'static>>;
+}
+
+impl HandlerError for GenericError<'static> {
+    fn handler_error(&self) -> Option<&GenericError<'static>> {
+        Some(self)
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct CallResponseRequest<'a, T> {
     pub service: Cow<'a, str>,
@@ -37,11 +117,62 @@ pub struct CallResponseRequest<'a, T> {
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchResult<'a, T> {
+    Data(T),
+    Error(GenericError<'a>),
+}
+
+impl<'a, T> From<Result<T, GenericError<'a>>> for BatchResult<'a, T> {
+    fn from(value: Result<T, GenericError<'a>>) -> Self {
+        match value {
+            Ok(data) => Self::Data(data),
+            Err(err) => Self::Error(err),
+        }
+    }
+}
+
+impl<'a, T> From<BatchResult<'a, T>> for Result<T, GenericError<'a>> {
+    fn from(value: BatchResult<'a, T>) -> Self {
+        match value {
+            BatchResult::Data(data) => Ok(data),
+            BatchResult::Error(err) => Err(err),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct GenericError<'a> {
     pub code: Cow<'a, str>,
     pub message: Cow<'a, str>,
 }
 
+impl<'a> GenericError<'a> {
+    pub fn new(code: impl Into<Cow<'a, str>>, message: impl Into<Cow<'a, str>>) -> Self {
+        Self {
+            code: code.into(),
+            message: message.into(),
+        }
+    }
+}
+
+/// Standard [`GenericError`] codes that are raised by netfn itself rather than by handlers.
+pub mod error_codes {
+    /// The requested service or fn does not exist.
+    pub const NOT_FOUND: &str = "not_found";
+    /// The request could not be decoded into a call for the target service.
+    pub const BAD_REQUEST: &str = "bad_request";
+    /// The handler ran, but its response could not be encoded.
+    pub const INTERNAL: &str = "internal";
+    /// The fn exists, but cannot be called in the way it was requested, such as calling a fn
+    /// that is not read-only through a read-only request.
+    pub const NOT_ALLOWED: &str = "not_allowed";
+    /// The caller does not have the permission that the fn requires.
+    pub const FORBIDDEN: &str = "forbidden";
+    /// The server is handling too many calls, so the call was rejected without being run.
+    pub const OVERLOADED: &str = "overloaded";
+}
+
 impl Display for GenericError<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "[{}] {}", self.code, self.message)
@@ -61,6 +192,7 @@ pub enum TunnelMessage<'a, T> {
     StreamClose(TunnelStreamClose),
     Error(TunnelCallError<'a>),
     StreamError(TunnelStreamError<'a>),
+    StreamOpenError(TunnelStreamOpenError<'a>),
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -117,6 +249,25 @@ pub struct TunnelStreamError<'a> {
     pub error: GenericError<'a>,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TunnelStreamOpenError<'a> {
+    #[serde(rename = "ref")]
+    pub msg_ref: u64,
+    pub error: GenericError<'a>,
+}
+
+/// A single frame of a stream sent over a call-response transport.
+///
+/// These match the tunnel stream messages, but without a handle, as the response itself is the
+/// stream.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StreamFrame<'a, T> {
+    StreamMessage { data: T },
+    StreamClose,
+    StreamError { error: GenericError<'a> },
+}
+
 #[cfg(not(target_arch = "wasm32"))]
 #[doc(hidden)]
 pub mod compat {
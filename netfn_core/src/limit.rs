@@ -0,0 +1,214 @@
+//! Limits on the calls that a [`Dispatcher`](crate::server::Dispatcher) will run.
+//!
+//! Calls over a limit are rejected straight away with an [`OVERLOADED`](error_codes::OVERLOADED)
+//! error rather than being queued, so clients can back off and retry.
+
+use std::{
+    sync::{
+        Arc, Mutex, PoisonError,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Instant,
+};
+
+use crate::{GenericError, error_codes};
+
+/// The rate that calls are allowed at, which is tracked as a token bucket.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+    per_second: u32,
+    burst: u32,
+}
+
+impl RateLimit {
+    /// Allows `calls` per second on average, with bursts of up to the same number of calls.
+    #[must_use]
+    pub fn per_second(calls: u32) -> Self {
+        Self {
+            per_second: calls,
+            burst: calls,
+        }
+    }
+
+    /// Sets the number of calls that can be made at once after a quiet period.
+    #[must_use]
+    pub fn burst(mut self, calls: u32) -> Self {
+        self.burst = calls;
+        self
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct TokenBucket {
+    limit: RateLimit,
+    state: Mutex<BucketState>,
+}
+
+#[derive(Debug)]
+struct BucketState {
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            state: Mutex::new(BucketState {
+                tokens: f64::from(limit.burst),
+                refilled: Instant::now(),
+            }),
+        }
+    }
+
+    /// Takes a token if there is one left.
+    pub(crate) fn try_take(&self) -> bool {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let now = Instant::now();
+        let elapsed = now.duration_since(state.refilled).as_secs_f64();
+        state.tokens = (state.tokens + elapsed * f64::from(self.limit.per_second))
+            .min(f64::from(self.limit.burst));
+        state.refilled = now;
+
+        if state.tokens >= 1.0 {
+            state.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Counts the calls that are in flight, refusing any over the limit.
+///
+/// Clones share the same count, so they can be moved into the tasks that run the calls.
+#[derive(Clone, Debug)]
+pub struct InFlight {
+    max: usize,
+    current: Arc<AtomicUsize>,
+}
+
+impl InFlight {
+    #[must_use]
+    pub fn new(max: usize) -> Self {
+        Self {
+            max,
+            current: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Allows any number of calls, while still counting them.
+    #[must_use]
+    pub fn unlimited() -> Self {
+        Self::new(usize::MAX)
+    }
+
+    /// Counts a call until the returned guard is dropped, or fails with an
+    /// [`OVERLOADED`](error_codes::OVERLOADED) error if the limit has been reached.
+    #[allow(clippy::missing_errors_doc)]
+    pub fn try_acquire(&self) -> Result<InFlightGuard, GenericError<'static>> {
+        self.current
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |current| {
+                (current < self.max).then_some(current + 1)
+            })
+            .map(|_| InFlightGuard {
+                current: self.current.clone(),
+            })
+            .map_err(|_| {
+                GenericError::new(
+                    error_codes::OVERLOADED,
+                    format!("too many calls in flight, the limit is {}", self.max),
+                )
+            })
+    }
+
+    #[must_use]
+    pub fn current(&self) -> usize {
+        self.current.load(Ordering::Acquire)
+    }
+}
+
+impl Default for InFlight {
+    fn default() -> Self {
+        Self::unlimited()
+    }
+}
+
+/// A call counted by an [`InFlight`], which stops being counted when this is dropped.
+#[derive(Debug)]
+#[must_use = "the call stops being counted when the guard is dropped"]
+pub struct InFlightGuard {
+    current: Arc<AtomicUsize>,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.current.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// The calls in flight on a single connection, created with
+/// [`Dispatcher::connection_limit`](crate::server::Dispatcher::connection_limit).
+///
+/// Transports add it to the extensions of every call from the connection, and the dispatcher
+/// counts those calls against it as well as its own limit.
+#[derive(Clone, Debug)]
+pub struct ConnectionLimit(pub(crate) InFlight);
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use super::*;
+
+    #[test]
+    fn bucket_allows_a_burst() {
+        let bucket = TokenBucket::new(RateLimit::per_second(1).burst(3));
+        assert!(bucket.try_take());
+        assert!(bucket.try_take());
+        assert!(bucket.try_take());
+        assert!(!bucket.try_take());
+    }
+
+    #[test]
+    fn bucket_refills_over_time() {
+        let bucket = TokenBucket::new(RateLimit::per_second(100).burst(1));
+        assert!(bucket.try_take());
+        assert!(!bucket.try_take());
+
+        thread::sleep(Duration::from_millis(50));
+        assert!(bucket.try_take());
+        assert!(!bucket.try_take());
+    }
+
+    #[test]
+    fn bucket_does_not_refill_past_its_burst() {
+        let bucket = TokenBucket::new(RateLimit::per_second(1000).burst(2));
+        thread::sleep(Duration::from_millis(20));
+        assert!(bucket.try_take());
+        assert!(bucket.try_take());
+        assert!(!bucket.try_take());
+    }
+
+    #[test]
+    fn in_flight_rejects_over_the_limit() {
+        let in_flight = InFlight::new(2);
+        let first = in_flight.try_acquire().unwrap();
+        let _second = in_flight.try_acquire().unwrap();
+        assert_eq!(in_flight.current(), 2);
+
+        let err = in_flight.try_acquire().unwrap_err();
+        assert_eq!(err.code, error_codes::OVERLOADED);
+
+        drop(first);
+        assert_eq!(in_flight.current(), 1);
+        let _third = in_flight.try_acquire().unwrap();
+    }
+
+    #[test]
+    fn clones_share_a_count() {
+        let in_flight = InFlight::new(1);
+        let _guard = in_flight.clone().try_acquire().unwrap();
+        assert!(in_flight.try_acquire().is_err());
+    }
+}
@@ -0,0 +1,184 @@
+//! Hooks for reporting metrics about calls, for both clients and servers.
+//!
+//! Transports and dispatchers report calls to a [`MetricsRecorder`], which can forward them to
+//! whichever metrics library the app uses. The recorder is shared through a [`Metrics`] handle, so
+//! one recorder can be given to several transports.
+
+use std::{borrow::Cow, fmt, sync::Arc, time::Duration};
+
+use crate::compat;
+
+/// The label that servers report a call under when its service or fn doesn't exist, so that
+/// clients can't create new labels.
+pub const UNKNOWN_LABEL: &str = "<unknown>";
+
+/// Receives metrics about calls.
+///
+/// Every call that is started is also finished, even if it was cancelled, so the calls in flight
+/// are the ones that have started but not yet finished.
+pub trait MetricsRecorder: compat::NetfnSend + compat::NetfnSync {
+    fn call_started(&self, side: Side, service: &str, fn_name: &str);
+
+    fn call_finished(&self, call: &CallMetrics<'_>);
+
+    /// Called when a transport connects again after its connection was lost, where `transport`
+    /// names the kind of transport, such as `websocket`.
+    fn reconnected(&self, transport: &str) {
+        let _ = transport;
+    }
+}
+
+/// Which end of a call is reporting it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Side {
+    Client,
+    Server,
+}
+
+impl Side {
+    #[must_use]
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Client => "client",
+            Self::Server => "server",
+        }
+    }
+}
+
+/// How a call ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome<'a> {
+    Ok,
+    /// The call failed with a [`GenericError`](crate::GenericError), such as one sent by the
+    /// server.
+    Error {
+        code: &'a str,
+    },
+    /// The transport failed, so there is no error code.
+    Failed,
+    /// The call was dropped before it finished.
+    Cancelled,
+}
+
+impl Outcome<'_> {
+    /// A short name for the outcome, which is the error code for errors.
+    #[must_use]
+    pub fn label(&self) -> &str {
+        match self {
+            Self::Ok => "ok",
+            Self::Error { code } => code,
+            Self::Failed => "failed",
+            Self::Cancelled => "cancelled",
+        }
+    }
+}
+
+/// The sizes of a call's encoded payloads in bytes, where the reporter knows them.
+///
+/// Sizes are measured before any compression.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PayloadSizes {
+    pub request: Option<usize>,
+    pub response: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct CallMetrics<'a> {
+    pub side: Side,
+    pub service: &'a str,
+    pub fn_name: &'a str,
+    pub outcome: Outcome<'a>,
+    /// How long the call took, which isn't measured on wasm32 as it has no clock in std.
+    pub duration: Option<Duration>,
+    pub sizes: PayloadSizes,
+}
+
+/// A shared handle to a [`MetricsRecorder`].
+#[derive(Clone)]
+pub struct Metrics(Arc<dyn MetricsRecorder>);
+
+impl Metrics {
+    pub fn new(recorder: impl MetricsRecorder + 'static) -> Self {
+        Self(Arc::new(recorder))
+    }
+
+    /// Reports that a call has started, returning a timer that reports it finishing.
+    ///
+    /// If the timer is dropped without being finished, the call is reported as
+    /// [`Outcome::Cancelled`].
+    pub fn start<'a>(
+        &self,
+        side: Side,
+        service: impl Into<Cow<'a, str>>,
+        fn_name: impl Into<Cow<'a, str>>,
+    ) -> CallTimer<'a> {
+        let service = service.into();
+        let fn_name = fn_name.into();
+        self.0.call_started(side, &service, &fn_name);
+
+        CallTimer {
+            metrics: self.clone(),
+            side,
+            service,
+            fn_name,
+            #[cfg(not(target_arch = "wasm32"))]
+            started: std::time::Instant::now(),
+            finished: false,
+        }
+    }
+
+    pub fn reconnected(&self, transport: &str) {
+        self.0.reconnected(transport);
+    }
+}
+
+impl fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Metrics")
+    }
+}
+
+/// A call that has been reported as started, created with [`Metrics::start`].
+#[derive(Debug)]
+#[must_use = "the call is reported as cancelled when the timer is dropped"]
+pub struct CallTimer<'a> {
+    metrics: Metrics,
+    side: Side,
+    service: Cow<'a, str>,
+    fn_name: Cow<'a, str>,
+    #[cfg(not(target_arch = "wasm32"))]
+    started: std::time::Instant,
+    finished: bool,
+}
+
+impl CallTimer<'_> {
+    pub fn finish(mut self, outcome: Outcome<'_>, sizes: PayloadSizes) {
+        self.report(outcome, sizes);
+    }
+
+    fn report(&mut self, outcome: Outcome<'_>, sizes: PayloadSizes) {
+        self.finished = true;
+
+        #[cfg(not(target_arch = "wasm32"))]
+        let duration = Some(self.started.elapsed());
+        #[cfg(target_arch = "wasm32")]
+        let duration = None;
+
+        self.metrics.0.call_finished(&CallMetrics {
+            side: self.side,
+            service: &self.service,
+            fn_name: &self.fn_name,
+            outcome,
+            duration,
+            sizes,
+        });
+    }
+}
+
+impl Drop for CallTimer<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.report(Outcome::Cancelled, PayloadSizes::default());
+        }
+    }
+}
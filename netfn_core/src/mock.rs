@@ -0,0 +1,278 @@
+//! Support for the mocks generated with `#[netfn::service(mock)]`.
+//!
+//! Each fn of a mock has a list of expectations, which are matched against calls in the order
+//! that they were added. A mock can be used directly as an implementation of its trait, or as a
+//! service through a [`Dispatcher`](crate::server::Dispatcher), which is also a transport.
+
+use std::sync::{Arc, Mutex, PoisonError};
+
+type Matcher<A> = Box<dyn Fn(&A) -> bool + Send + Sync>;
+type Returner<A, R> = Arc<dyn Fn(A) -> R + Send + Sync>;
+
+/// The expectations for a single fn of a mock, where `A` is a tuple of its args.
+pub struct MockFn<A, R> {
+    name: &'static str,
+    expectations: Mutex<Vec<Expectation<A, R>>>,
+}
+
+impl<A, R> MockFn<A, R> {
+    #[must_use]
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            expectations: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Adds an expectation, which is only used for calls that don't match any added before it.
+    pub fn expect(&mut self) -> &mut Expectation<A, R> {
+        let expectations = self
+            .expectations
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        let index = expectations.len();
+        expectations.push(Expectation::default());
+        &mut expectations[index]
+    }
+
+    /// Calls the first expectation that matches the args and hasn't been called as many times as
+    /// it expects.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no expectation matches, if an expectation set with
+    /// [`never`](Expectation::never) matches before any other, or if the matching one doesn't
+    /// have a return value.
+    pub fn call(&self, args: A) -> R {
+        let returns = {
+            let mut expectations = self
+                .expectations
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let Some(index) = expectations
+                .iter()
+                .position(|exp| exp.forbids(&args) || exp.matches(&args))
+            else {
+                panic!("{} was called, but no expectation matched", self.name);
+            };
+
+            let expectation = &mut expectations[index];
+            assert!(
+                !expectation.forbidden,
+                "{} was called, but expectation {index} expects it never to be",
+                self.name
+            );
+            expectation.calls += 1;
+
+            let Some(returns) = expectation.returns.clone() else {
+                panic!(
+                    "{} was called, but its expectation has no return value",
+                    self.name
+                );
+            };
+            returns
+        };
+
+        returns(args)
+    }
+
+    /// The number of calls that have matched an expectation.
+    pub fn calls(&self) -> usize {
+        self.expectations
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|exp| exp.calls)
+            .sum()
+    }
+
+    /// Checks that every expectation with a set number of calls has been called that many times.
+    ///
+    /// This is also done when the mock is dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if an expectation hasn't been called enough times.
+    pub fn checkpoint(&self) {
+        let expectations = self
+            .expectations
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        for (i, exp) in expectations.iter().enumerate() {
+            if let Some(times) = exp.times
+                && exp.calls != times
+            {
+                panic!(
+                    "{} expectation {i} should have been called {times} time(s), but was called {} time(s)",
+                    self.name, exp.calls,
+                );
+            }
+        }
+    }
+}
+
+impl<A, R> Drop for MockFn<A, R> {
+    fn drop(&mut self) {
+        // Panicking again would abort the test rather than reporting the original failure.
+        if !std::thread::panicking() {
+            self.checkpoint();
+        }
+    }
+}
+
+/// A single expectation for a fn, which is set up like a builder.
+pub struct Expectation<A, R> {
+    matcher: Option<Matcher<A>>,
+    returns: Option<Returner<A, R>>,
+    times: Option<usize>,
+    forbidden: bool,
+    calls: usize,
+}
+
+impl<A, R> Default for Expectation<A, R> {
+    fn default() -> Self {
+        Self {
+            matcher: None,
+            returns: None,
+            times: None,
+            forbidden: false,
+            calls: 0,
+        }
+    }
+}
+
+impl<A, R> Expectation<A, R> {
+    /// Only matches calls where the args pass the matcher.
+    pub fn with(&mut self, matcher: impl Fn(&A) -> bool + Send + Sync + 'static) -> &mut Self {
+        self.matcher = Some(Box::new(matcher));
+        self
+    }
+
+    /// Responds to calls using their args.
+    pub fn returning(&mut self, returns: impl Fn(A) -> R + Send + Sync + 'static) -> &mut Self {
+        self.returns = Some(Arc::new(returns));
+        self
+    }
+
+    /// Responds to every call with the same value.
+    pub fn return_const(&mut self, value: R) -> &mut Self
+    where
+        R: Clone + Send + Sync + 'static,
+    {
+        self.returning(move |_| value.clone())
+    }
+
+    /// Expects exactly this many calls, after which the expectation stops matching.
+    pub fn times(&mut self, times: usize) -> &mut Self {
+        self.times = Some(times);
+        self
+    }
+
+    /// Expects no calls, panicking if a matching call is made rather than passing it on to later
+    /// expectations.
+    pub fn never(&mut self) -> &mut Self {
+        self.forbidden = true;
+        self
+    }
+
+    fn matches_args(&self, args: &A) -> bool {
+        self.matcher.as_ref().is_none_or(|matcher| matcher(args))
+    }
+
+    fn forbids(&self, args: &A) -> bool {
+        self.forbidden && self.matches_args(args)
+    }
+
+    fn matches(&self, args: &A) -> bool {
+        !self.forbidden
+            && self.times.is_none_or(|times| self.calls < times)
+            && self.matches_args(args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mock() -> MockFn<(u32,), u32> {
+        MockFn::new("Test::double")
+    }
+
+    #[test]
+    fn expectations_match_in_order() {
+        let mut double = mock();
+        double.expect().with(|(n,)| *n > 10).return_const(0);
+        double.expect().returning(|(n,)| n * 2);
+
+        assert_eq!(double.call((11,)), 0);
+        assert_eq!(double.call((2,)), 4);
+        assert_eq!(double.calls(), 2);
+    }
+
+    #[test]
+    fn times_stops_matching_once_used() {
+        let mut double = mock();
+        double.expect().return_const(1).times(2);
+        double.expect().return_const(2);
+
+        assert_eq!(double.call((0,)), 1);
+        assert_eq!(double.call((0,)), 1);
+        assert_eq!(double.call((0,)), 2);
+        double.checkpoint();
+    }
+
+    #[test]
+    #[should_panic(expected = "expectation 0 should have been called 2 time(s), but was called 1")]
+    fn checkpoint_fails_with_too_few_calls() {
+        let mut double = mock();
+        double.expect().return_const(1).times(2);
+
+        double.call((0,));
+        double.checkpoint();
+    }
+
+    #[test]
+    #[should_panic(expected = "should have been called 1 time(s), but was called 0")]
+    fn dropping_checks_the_calls() {
+        let mut double = mock();
+        double.expect().return_const(1).times(1);
+    }
+
+    #[test]
+    #[should_panic(expected = "expectation 0 expects it never to be")]
+    fn never_panics_on_a_matching_call() {
+        let mut double = mock();
+        double.expect().with(|(n,)| *n == 0).never();
+        double.expect().return_const(1);
+
+        double.call((0,));
+    }
+
+    #[test]
+    fn never_ignores_other_calls() {
+        let mut double = mock();
+        double.expect().with(|(n,)| *n == 0).never();
+        double.expect().return_const(1);
+
+        assert_eq!(double.call((1,)), 1);
+        double.checkpoint();
+    }
+
+    #[test]
+    #[should_panic(expected = "no expectation matched")]
+    fn unmatched_calls_panic() {
+        let mut double = mock();
+        double.expect().with(|(n,)| *n == 0).return_const(1);
+
+        double.call((1,));
+    }
+
+    #[test]
+    #[should_panic(expected = "has no return value")]
+    fn expectations_without_a_return_value_panic() {
+        let mut double = mock();
+        double.expect();
+
+        double.call((1,));
+    }
+}
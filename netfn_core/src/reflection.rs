@@ -0,0 +1,81 @@
+//! The reflection service, which describes the services that a server provides.
+//!
+//! It is called like any other service, so it works over every transport. Servers using the
+//! [`Dispatcher`](crate::server::Dispatcher) enable it with `with_reflection`.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{ServiceDescriptor, ServiceRequest, Transport};
+
+/// The name that the reflection service is served under.
+pub const REFLECTION_SERVICE: &str = "netfn.Reflection";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "fn", content = "args")]
+pub enum ReflectionRequest {
+    /// Lists the names of every service, returning a [`ServiceList`].
+    ListServices {},
+    /// Describes a single service, returning its [`ServiceDescriptor`].
+    DescribeService {
+        #[serde(rename = "0")]
+        name: String,
+    },
+}
+
+impl ServiceRequest for ReflectionRequest {
+    fn fn_name(&self) -> &'static str {
+        match *self {
+            Self::ListServices {} => "ListServices",
+            Self::DescribeService { .. } => "DescribeService",
+        }
+    }
+
+    fn read_only(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServiceList {
+    pub services: Vec<String>,
+    /// Stream services don't have descriptors yet, so only their names are known.
+    pub stream_services: Vec<String>,
+}
+
+/// A client for the reflection service.
+pub struct ReflectionClient<T>
+where
+    T: Transport,
+{
+    transport: T,
+}
+
+impl<T> ReflectionClient<T>
+where
+    T: Transport,
+{
+    pub fn new(transport: T) -> Self {
+        Self { transport }
+    }
+
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn list_services(&self) -> Result<ServiceList, T::Error> {
+        self.transport
+            .call(REFLECTION_SERVICE, ReflectionRequest::ListServices {})
+            .await
+    }
+
+    /// Describes a service, which fails with a `not_found` error if it doesn't exist.
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn describe_service(
+        &self,
+        name: impl Into<String>,
+    ) -> Result<ServiceDescriptor<'static>, T::Error> {
+        self.transport
+            .call(
+                REFLECTION_SERVICE,
+                ReflectionRequest::DescribeService { name: name.into() },
+            )
+            .await
+    }
+}
@@ -0,0 +1,82 @@
+//! JSON Schema generation for services, using [`schemars`].
+//!
+//! This is enabled for a service with `#[netfn::service(schema)]`, which derives
+//! [`JsonSchema`] for the generated request, response and args types.
+
+use schemars::{JsonSchema, Schema, SchemaGenerator, generate::SchemaSettings};
+use serde_json::{Map, Value};
+
+use crate::ServiceDescriptor;
+
+/// The schemas of a single fn's args and return type.
+#[derive(Debug, Clone)]
+pub struct FnSchema {
+    /// The name of the fn as it appears on the wire.
+    pub name: &'static str,
+    pub args: Schema,
+    pub returns: Schema,
+}
+
+/// Implemented by the request enums of services that have schema generation enabled.
+pub trait ServiceSchema: JsonSchema {
+    const DESCRIPTOR: ServiceDescriptor<'static>;
+
+    /// Generates the schemas for every fn, adding any types they use to `generator`.
+    fn fn_schemas(generator: &mut SchemaGenerator) -> Vec<FnSchema>;
+}
+
+/// Generates a JSON Schema document for a service, using the draft 2020-12 meta-schema.
+///
+/// The document itself validates a [`CallResponseRequest`](crate::CallResponseRequest) to the
+/// service, and lists the args and return type of each fn under `x-netfn-fns`, which
+/// validators will ignore. Every type is defined under `$defs`.
+#[must_use]
+pub fn service_schema<R>() -> Schema
+where
+    R: ServiceSchema,
+{
+    let mut generator = SchemaSettings::draft2020_12().into_generator();
+    let descriptor = R::DESCRIPTOR;
+
+    let call = generator.subschema_for::<R>();
+    let fns: Map<String, Value> = R::fn_schemas(&mut generator)
+        .into_iter()
+        .map(|tfn| {
+            let mut schema = Map::from_iter([
+                ("args".to_owned(), tfn.args.to_value()),
+                ("returns".to_owned(), tfn.returns.to_value()),
+            ]);
+            if let Some(desc) = descriptor.get(tfn.name) {
+                if !desc.docs.is_empty() {
+                    schema.insert("description".to_owned(), desc.docs.clone().into());
+                }
+                schema.insert("readOnly".to_owned(), desc.read_only.into());
+            }
+            (tfn.name.to_owned(), Value::Object(schema))
+        })
+        .collect();
+
+    let mut schema = schemars::json_schema!({
+        "title": descriptor.name,
+        "type": "object",
+        "properties": {
+            "service": { "const": descriptor.name },
+            "call": call,
+        },
+        "required": ["service", "call"],
+        "x-netfn-fns": fns,
+    });
+
+    if let Some(meta_schema) = generator.settings().meta_schema.as_deref() {
+        schema.insert("$schema".to_owned(), meta_schema.into());
+    }
+    if !descriptor.docs.is_empty() {
+        schema.insert("description".to_owned(), descriptor.docs.into());
+    }
+    schema.insert(
+        "$defs".to_owned(),
+        Value::Object(generator.take_definitions(true)),
+    );
+
+    schema
+}
@@ -0,0 +1,1033 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt, io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures::{
+    Stream, StreamExt as _,
+    future::{self, BoxFuture, FutureExt as _},
+    stream::BoxStream,
+};
+pub use http::Extensions;
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::Value;
+
+use crate::{
+    BatchResult, CallResponseRequest, GenericError, Service, ServiceDescriptor, ServiceRequest,
+    Transport, compat, error_codes,
+    limit::{ConnectionLimit, InFlight, InFlightGuard, RateLimit, TokenBucket},
+    metrics::{Metrics, Outcome, PayloadSizes, Side, UNKNOWN_LABEL},
+    reflection::{REFLECTION_SERVICE, ReflectionRequest, ServiceList},
+};
+
+pub type DispatchResult = Result<Value, GenericError<'static>>;
+pub type DispatchStream = BoxStream<'static, DispatchResult>;
+
+/// A service that responds to a call with a stream of items rather than a single response.
+///
+/// Streams are routed separately to calls, so a stream service may share its name with a
+/// [`Service`] to provide both for the same fns.
+pub trait StreamService {
+    const NAME: &'static str;
+    /// The fns that requests may name, which are the only ones that streams are reported under in
+    /// [`Metrics`].
+    const FNS: &'static [&'static str] = &[];
+    type Request;
+    type Item;
+
+    /// Opens a stream for the request.
+    ///
+    /// The stream is not tied to the service, so any state it needs should be cloned into it.
+    /// Yielding an error will end the stream.
+    fn open(
+        &self,
+        request: Self::Request,
+    ) -> impl Stream<Item = Result<Self::Item, GenericError<'static>>> + Send + 'static;
+
+    /// The permission needed to open a stream for the request, which is checked by the
+    /// [`Authorizer`] in the same way as fns marked with `#[netfn(requires = "...")]`.
+    fn requires(&self, request: &Self::Request) -> Option<&'static str> {
+        let _ = request;
+        None
+    }
+}
+
+/// A call that is being checked by an [`Authorizer`].
+#[derive(Clone, Copy, Debug)]
+pub struct CallContext<'a> {
+    pub service: &'a str,
+    pub fn_name: &'a str,
+    /// Values attached to the call by the transport, such as the request extensions of the HTTP
+    /// server.
+    pub extensions: &'a Extensions,
+}
+
+/// Decides whether a call to a fn marked with `#[netfn(requires = "...")]` is allowed.
+///
+/// Authorizers only check permissions, so callers should already have been authenticated, such as
+/// by a middleware that adds the user to the request extensions. Calls that are denied fail with
+/// a [`FORBIDDEN`](error_codes::FORBIDDEN) error before reaching the handler.
+pub trait Authorizer: Send + Sync {
+    fn authorize(&self, context: &CallContext<'_>, requires: &str) -> bool;
+}
+
+impl<F> Authorizer for F
+where
+    F: Fn(&CallContext<'_>, &str) -> bool + Send + Sync,
+{
+    fn authorize(&self, context: &CallContext<'_>, requires: &str) -> bool {
+        self(context, requires)
+    }
+}
+
+/// Routes type-erased calls to the services that have been registered with it.
+///
+/// Calls are passed through as [`serde_json::Value`]s, which allows any self-describing
+/// encoding to be used by the transport sitting in front of the dispatcher.
+#[derive(Default)]
+pub struct Dispatcher {
+    services: HashMap<&'static str, Box<dyn ErasedService>>,
+    streams: HashMap<&'static str, Box<dyn ErasedStreamService>>,
+    authorizer: Option<Box<dyn Authorizer>>,
+    concurrency: InFlight,
+    max_in_flight_per_connection: Option<usize>,
+    rate_limits: HashMap<&'static str, ServiceRateLimits>,
+    metrics: Option<Metrics>,
+    reflection: bool,
+}
+
+#[derive(Default)]
+struct ServiceRateLimits {
+    service: Option<TokenBucket>,
+    fns: HashMap<&'static str, TokenBucket>,
+}
+
+impl Dispatcher {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a service, replacing any existing service with the same name.
+    #[must_use]
+    pub fn with_service<S>(mut self, service: S) -> Self
+    where
+        S: Service + Send + Sync + 'static,
+        S::Request: DeserializeOwned + ServiceRequest,
+        S::Response: Serialize,
+    {
+        self.services.insert(S::NAME, Box::new(service));
+        self
+    }
+
+    /// Registers a stream service, replacing any existing stream service with the same name.
+    #[must_use]
+    pub fn with_stream_service<S>(mut self, service: S) -> Self
+    where
+        S: StreamService + Send + Sync + 'static,
+        S::Request: DeserializeOwned,
+        S::Item: Serialize,
+    {
+        self.streams.insert(S::NAME, Box::new(service));
+        self
+    }
+
+    /// Serves the [reflection service](crate::reflection), which lets clients list the
+    /// registered services and get their descriptors.
+    #[must_use]
+    pub fn with_reflection(mut self) -> Self {
+        self.reflection = true;
+        self
+    }
+
+    /// Sets the authorizer for fns that require a permission.
+    ///
+    /// Without an authorizer, every call to those fns is denied.
+    #[must_use]
+    pub fn with_authorizer(mut self, authorizer: impl Authorizer + 'static) -> Self {
+        self.authorizer = Some(Box::new(authorizer));
+        self
+    }
+
+    /// Limits the number of calls that are run at once, across every connection.
+    ///
+    /// Open streams count as calls until they end.
+    #[must_use]
+    pub fn with_max_concurrency(mut self, max: usize) -> Self {
+        self.concurrency = InFlight::new(max);
+        self
+    }
+
+    /// Limits the number of calls in flight on each connection, so that one connection can't take
+    /// every slot of [`with_max_concurrency`](Self::with_max_concurrency).
+    ///
+    /// This is only enforced for calls whose extensions hold the
+    /// [`connection_limit`](Self::connection_limit) of their connection, which transports that
+    /// keep connections open add to them.
+    #[must_use]
+    pub fn with_max_in_flight_per_connection(mut self, max: usize) -> Self {
+        self.max_in_flight_per_connection = Some(max);
+        self
+    }
+
+    /// Limits the rate of calls to a service, including streams opened on a stream service with
+    /// the same name.
+    #[must_use]
+    pub fn with_rate_limit(mut self, service: &'static str, limit: RateLimit) -> Self {
+        self.rate_limits.entry(service).or_default().service = Some(TokenBucket::new(limit));
+        self
+    }
+
+    /// Limits the rate of calls to a single fn, which applies on top of any limit on its service.
+    #[must_use]
+    pub fn with_fn_rate_limit(
+        mut self,
+        service: &'static str,
+        fn_name: &'static str,
+        limit: RateLimit,
+    ) -> Self {
+        self.rate_limits
+            .entry(service)
+            .or_default()
+            .fns
+            .insert(fn_name, TokenBucket::new(limit));
+        self
+    }
+
+    /// Reports every call that is dispatched, including the ones that are rejected, and every
+    /// stream that is opened.
+    ///
+    /// Calls to services or fns that don't exist are reported under [`UNKNOWN_LABEL`], as are
+    /// streams that name fns missing from [`StreamService::FNS`]. Payload sizes are those of the JSON encoding of the call and its
+    /// response, as calls are encoded by the transport.
+    #[must_use]
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Creates the counter for the calls in flight on a new connection, which should be added to
+    /// the extensions of every call dispatched from it.
+    #[must_use]
+    pub fn connection_limit(&self) -> ConnectionLimit {
+        ConnectionLimit(
+            self.max_in_flight_per_connection
+                .map_or_else(InFlight::unlimited, InFlight::new),
+        )
+    }
+
+    pub fn services(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.services.keys().copied()
+    }
+
+    pub fn stream_services(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.streams.keys().copied()
+    }
+
+    pub fn descriptors(&self) -> impl Iterator<Item = ServiceDescriptor<'static>> + '_ {
+        self.services.values().map(|service| service.descriptor())
+    }
+
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn dispatch(&self, request: CallResponseRequest<'_, Value>) -> DispatchResult {
+        self.dispatch_with(request, &Extensions::new()).await
+    }
+
+    /// Dispatches a call with extensions that are passed to the [`Authorizer`].
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn dispatch_with(
+        &self,
+        request: CallResponseRequest<'_, Value>,
+        extensions: &Extensions,
+    ) -> DispatchResult {
+        self.dispatch_inner(request, false, extensions, InFlightCheck::Acquire)
+            .await
+    }
+
+    /// Dispatches a call only if the fn has been marked as read-only, which is needed for
+    /// requests that may be cached or replayed by anything between the client and server.
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn dispatch_read_only(
+        &self,
+        request: CallResponseRequest<'_, Value>,
+    ) -> DispatchResult {
+        self.dispatch_read_only_with(request, &Extensions::new())
+            .await
+    }
+
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn dispatch_read_only_with(
+        &self,
+        request: CallResponseRequest<'_, Value>,
+        extensions: &Extensions,
+    ) -> DispatchResult {
+        self.dispatch_inner(request, true, extensions, InFlightCheck::Acquire)
+            .await
+    }
+
+    async fn dispatch_inner(
+        &self,
+        request: CallResponseRequest<'_, Value>,
+        read_only: bool,
+        extensions: &Extensions,
+        in_flight: InFlightCheck,
+    ) -> DispatchResult {
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!(
+            "netfn.dispatch",
+            service = %request.service,
+            fn_name = request.call.get("fn").and_then(serde_json::Value::as_str),
+        );
+
+        let timer = self.metrics.as_ref().map(|metrics| {
+            let (service, fn_name) = self.metric_labels(&request);
+            (
+                metrics.start(Side::Server, service, fn_name),
+                json_size(&request),
+            )
+        });
+
+        let call = async {
+            let result = self
+                .dispatch_call(request, read_only, extensions, in_flight)
+                .await;
+            if let Some((timer, request_size)) = timer {
+                let (outcome, response_size) = match &result {
+                    Ok(response) => (Outcome::Ok, json_size(response)),
+                    Err(err) => (Outcome::Error { code: &err.code }, json_size(err)),
+                };
+                timer.finish(
+                    outcome,
+                    PayloadSizes {
+                        request: request_size,
+                        response: response_size,
+                    },
+                );
+            }
+            #[cfg(feature = "tracing")]
+            if let Err(err) = &result {
+                // Internal errors are bugs in the server, while the rest are caused by the caller
+                if err.code == error_codes::INTERNAL {
+                    tracing::warn!(code = %err.code, message = %err.message, "call failed");
+                } else {
+                    tracing::debug!(code = %err.code, message = %err.message, "call failed");
+                }
+            }
+            result
+        };
+
+        #[cfg(feature = "tracing")]
+        let call = tracing::Instrument::instrument(call, span);
+        call.await
+    }
+
+    async fn dispatch_call(
+        &self,
+        request: CallResponseRequest<'_, Value>,
+        read_only: bool,
+        extensions: &Extensions,
+        in_flight: InFlightCheck,
+    ) -> DispatchResult {
+        let _in_flight = match in_flight {
+            InFlightCheck::Acquire => Some(self.acquire(extensions)?),
+            InFlightCheck::Batch(result) => {
+                result?;
+                None
+            }
+        };
+        self.check_rate_limits(&request.service, &request.call)?;
+
+        if self.reflection && request.service == REFLECTION_SERVICE {
+            return self.reflect(request.call);
+        }
+
+        let Some(service) = self.services.get(&*request.service) else {
+            return Err(GenericError::new(
+                error_codes::NOT_FOUND,
+                format!("service {} does not exist", request.service),
+            ));
+        };
+
+        let checks = Checks {
+            read_only,
+            authorizer: self.authorizer.as_deref(),
+            extensions,
+        };
+        service.call(request.call, &checks).await
+    }
+
+    /// Counts a call against the limit on calls in flight, and the limit of its connection if it
+    /// has one.
+    fn acquire(&self, extensions: &Extensions) -> Result<CallGuard, GenericError<'static>> {
+        let connection = extensions
+            .get::<ConnectionLimit>()
+            .map(|ConnectionLimit(limit)| limit.try_acquire())
+            .transpose()?;
+
+        Ok(CallGuard {
+            _global: self.concurrency.try_acquire()?,
+            _connection: connection,
+        })
+    }
+
+    /// Finds the service and fn to report a call under, which are only taken from the call if
+    /// they exist.
+    fn metric_labels(
+        &self,
+        request: &CallResponseRequest<'_, Value>,
+    ) -> (Cow<'static, str>, Cow<'static, str>) {
+        let fn_name = request.call.get("fn").and_then(Value::as_str);
+
+        if self.reflection && request.service == REFLECTION_SERVICE {
+            let fn_name = fn_name.and_then(|name| REFLECTION_FNS.into_iter().find(|&f| f == name));
+            return (
+                REFLECTION_SERVICE.into(),
+                fn_name.unwrap_or(UNKNOWN_LABEL).into(),
+            );
+        }
+
+        let Some((&name, service)) = self.services.get_key_value(&*request.service) else {
+            return (UNKNOWN_LABEL.into(), UNKNOWN_LABEL.into());
+        };
+        let fn_name = fn_name
+            .and_then(|fn_name| Some(service.descriptor().get(fn_name)?.name.clone()))
+            .unwrap_or(Cow::Borrowed(UNKNOWN_LABEL));
+        (name.into(), fn_name)
+    }
+
+    /// Finds the service and fn to report a stream under, which are only taken from the call if
+    /// the stream service lists them.
+    fn stream_metric_labels(
+        &self,
+        request: &CallResponseRequest<'_, Value>,
+    ) -> (&'static str, &'static str) {
+        let Some((&name, service)) = self.streams.get_key_value(&*request.service) else {
+            return (UNKNOWN_LABEL, UNKNOWN_LABEL);
+        };
+        let fn_name = request
+            .call
+            .get("fn")
+            .and_then(Value::as_str)
+            .and_then(|fn_name| service.fns().iter().find(|&&f| f == fn_name))
+            .copied()
+            .unwrap_or(UNKNOWN_LABEL);
+        (name, fn_name)
+    }
+
+    /// Takes a call from the buckets of its service and fn, which is done before decoding it so
+    /// that excess calls are as cheap as possible to reject.
+    fn check_rate_limits(&self, service: &str, call: &Value) -> Result<(), GenericError<'static>> {
+        let Some(limits) = self.rate_limits.get(service) else {
+            return Ok(());
+        };
+
+        if let Some(bucket) = &limits.service
+            && !bucket.try_take()
+        {
+            return Err(GenericError::new(
+                error_codes::OVERLOADED,
+                format!("rate limit exceeded for service {service}"),
+            ));
+        }
+
+        if let Some(fn_name) = call.get("fn").and_then(Value::as_str)
+            && let Some(bucket) = limits.fns.get(fn_name)
+            && !bucket.try_take()
+        {
+            return Err(GenericError::new(
+                error_codes::OVERLOADED,
+                format!("rate limit exceeded for fn {fn_name} in service {service}"),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn reflect(&self, call: Value) -> DispatchResult {
+        let request: ReflectionRequest = serde_json::from_value(call)
+            .map_err(|err| GenericError::new(error_codes::BAD_REQUEST, err.to_string()))?;
+
+        let response = match request {
+            ReflectionRequest::ListServices {} => {
+                let mut list = ServiceList {
+                    services: self.services().map(str::to_owned).collect(),
+                    stream_services: self.stream_services().map(str::to_owned).collect(),
+                };
+                list.services.sort_unstable();
+                list.stream_services.sort_unstable();
+                serde_json::to_value(list)
+            }
+            ReflectionRequest::DescribeService { name } => {
+                let Some(service) = self.services.get(&*name) else {
+                    return Err(GenericError::new(
+                        error_codes::NOT_FOUND,
+                        format!("service {name} does not exist"),
+                    ));
+                };
+                serde_json::to_value(service.descriptor())
+            }
+        };
+
+        response.map_err(|err| GenericError::new(error_codes::INTERNAL, err.to_string()))
+    }
+
+    /// Opens a stream on the target stream service.
+    #[allow(clippy::missing_errors_doc)]
+    pub fn dispatch_stream(
+        &self,
+        request: CallResponseRequest<'_, Value>,
+    ) -> Result<DispatchStream, GenericError<'static>> {
+        self.dispatch_stream_with(request, &Extensions::new())
+    }
+
+    /// Opens a stream with extensions that are passed to the [`Authorizer`].
+    #[allow(clippy::missing_errors_doc)]
+    pub fn dispatch_stream_with(
+        &self,
+        request: CallResponseRequest<'_, Value>,
+        extensions: &Extensions,
+    ) -> Result<DispatchStream, GenericError<'static>> {
+        let Some(metrics) = &self.metrics else {
+            return self.open_stream(request, extensions);
+        };
+
+        // Only opening the stream is timed, as streams may stay open for any length of time
+        let (service, fn_name) = self.stream_metric_labels(&request);
+        let timer = metrics.start(Side::Server, service, fn_name);
+        let request_size = json_size(&request);
+
+        let result = self.open_stream(request, extensions);
+        let (outcome, response_size) = match &result {
+            Ok(_) => (Outcome::Ok, None),
+            Err(err) => (Outcome::Error { code: &err.code }, json_size(err)),
+        };
+        timer.finish(
+            outcome,
+            PayloadSizes {
+                request: request_size,
+                response: response_size,
+            },
+        );
+        result
+    }
+
+    fn open_stream(
+        &self,
+        request: CallResponseRequest<'_, Value>,
+        extensions: &Extensions,
+    ) -> Result<DispatchStream, GenericError<'static>> {
+        let in_flight = self.acquire(extensions)?;
+        self.check_rate_limits(&request.service, &request.call)?;
+
+        let Some(service) = self.streams.get(&*request.service) else {
+            return Err(GenericError::new(
+                error_codes::NOT_FOUND,
+                format!("stream service {} does not exist", request.service),
+            ));
+        };
+
+        let checks = Checks {
+            read_only: false,
+            authorizer: self.authorizer.as_deref(),
+            extensions,
+        };
+        let stream = service.open(request.call, &checks)?;
+        Ok(InFlightStream {
+            stream,
+            _in_flight: in_flight,
+        }
+        .boxed())
+    }
+
+    /// Dispatches all the calls concurrently, returning the results in the same order as the
+    /// requests.
+    ///
+    /// The batch counts as a single call against the limits on calls in flight, so either every
+    /// call in it is run or every one is rejected.
+    pub async fn dispatch_batch(
+        &self,
+        requests: Vec<CallResponseRequest<'_, Value>>,
+    ) -> Vec<BatchResult<'static, Value>> {
+        self.dispatch_batch_with(requests, &Extensions::new()).await
+    }
+
+    pub async fn dispatch_batch_with(
+        &self,
+        requests: Vec<CallResponseRequest<'_, Value>>,
+        extensions: &Extensions,
+    ) -> Vec<BatchResult<'static, Value>> {
+        let in_flight = self.acquire(extensions);
+        let admitted = in_flight.as_ref().map(|_| ()).map_err(Clone::clone);
+
+        future::join_all(requests.into_iter().map(|request| {
+            let check = InFlightCheck::Batch(admitted.clone());
+            async move {
+                self.dispatch_inner(request, false, extensions, check)
+                    .await
+                    .into()
+            }
+        }))
+        .await
+    }
+}
+
+/// Dispatchers can be used as an in-process transport, which is useful for testing clients without
+/// a network.
+///
+/// Calls still go through the same encoding as they would over a network, so the only difference
+/// is that nothing is sent anywhere.
+impl Transport for Dispatcher {
+    type Error = GenericError<'static>;
+
+    async fn call<Req, Res>(&self, service: &'static str, request: Req) -> Result<Res, Self::Error>
+    where
+        Req: compat::NetfnSend + Serialize + ServiceRequest,
+        Res: compat::NetfnSend + DeserializeOwned,
+    {
+        let call = serde_json::to_value(request)
+            .map_err(|err| GenericError::new(error_codes::BAD_REQUEST, err.to_string()))?;
+        let response = self
+            .dispatch(CallResponseRequest {
+                service: service.into(),
+                call,
+            })
+            .await?;
+        serde_json::from_value(response)
+            .map_err(|err| GenericError::new(error_codes::INTERNAL, err.to_string()))
+    }
+}
+
+impl fmt::Debug for Dispatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Dispatcher")
+            .field("services", &self.services.keys())
+            .field("streams", &self.streams.keys())
+            .field("authorizer", &self.authorizer.is_some())
+            .field("concurrency", &self.concurrency)
+            .field(
+                "max_in_flight_per_connection",
+                &self.max_in_flight_per_connection,
+            )
+            .field("rate_limits", &self.rate_limits.keys())
+            .field("metrics", &self.metrics.is_some())
+            .field("reflection", &self.reflection)
+            .finish()
+    }
+}
+
+/// The fns of the reflection service, which has no descriptor to find them in.
+const REFLECTION_FNS: [&str; 2] = ["ListServices", "DescribeService"];
+
+/// Measures the length of a value's JSON encoding without keeping it.
+fn json_size<T>(value: &T) -> Option<usize>
+where
+    T: Serialize + ?Sized,
+{
+    struct Counter(usize);
+
+    impl io::Write for Counter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0 += buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut counter = Counter(0);
+    serde_json::to_writer(&mut counter, value).ok()?;
+    Some(counter.0)
+}
+
+/// How a call is counted against the limits on calls in flight.
+enum InFlightCheck {
+    /// The call is counted on its own.
+    Acquire,
+    /// The call is part of a batch that has already been counted, or rejected with this error.
+    Batch(Result<(), GenericError<'static>>),
+}
+
+/// A call counted against the limit on calls in flight, and the limit of its connection.
+struct CallGuard {
+    _global: InFlightGuard,
+    _connection: Option<InFlightGuard>,
+}
+
+/// Keeps a stream counted as a call in flight until it is dropped.
+struct InFlightStream {
+    stream: DispatchStream,
+    _in_flight: CallGuard,
+}
+
+impl Stream for InFlightStream {
+    type Item = DispatchResult;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.stream.poll_next_unpin(cx)
+    }
+}
+
+/// The checks made on a call before it reaches the handler.
+struct Checks<'a> {
+    read_only: bool,
+    authorizer: Option<&'a dyn Authorizer>,
+    extensions: &'a Extensions,
+}
+
+impl Checks<'_> {
+    /// Asks the authorizer whether the call is allowed, which is always denied without one.
+    fn authorize(
+        &self,
+        service: &str,
+        fn_name: &str,
+        requires: &str,
+    ) -> Result<(), GenericError<'static>> {
+        let context = CallContext {
+            service,
+            fn_name,
+            extensions: self.extensions,
+        };
+        let allowed = self
+            .authorizer
+            .is_some_and(|authorizer| authorizer.authorize(&context, requires));
+
+        if allowed {
+            Ok(())
+        } else {
+            Err(GenericError::new(
+                error_codes::FORBIDDEN,
+                format!("fn {fn_name} requires {requires}"),
+            ))
+        }
+    }
+}
+
+trait ErasedService: Send + Sync {
+    fn call(&self, call: Value, checks: &Checks<'_>) -> BoxFuture<'_, DispatchResult>;
+    fn descriptor(&self) -> ServiceDescriptor<'static>;
+}
+
+impl<S> ErasedService for S
+where
+    S: Service + Send + Sync,
+    S::Request: DeserializeOwned + ServiceRequest,
+    S::Response: Serialize,
+{
+    fn call(&self, call: Value, checks: &Checks<'_>) -> BoxFuture<'_, DispatchResult> {
+        // Unknown fns would otherwise fail to decode as a bad request. Descriptors without any fns
+        // are skipped, as they may have been written by hand without listing them.
+        if let Some(name) = call.get("fn").and_then(Value::as_str)
+            && !S::DESCRIPTOR.fns.is_empty()
+            && S::DESCRIPTOR.get(name).is_none()
+        {
+            return future::ready(Err(GenericError::new(
+                error_codes::NOT_FOUND,
+                format!("fn {name} does not exist in service {}", S::NAME),
+            )))
+            .boxed();
+        }
+
+        let request: S::Request = match serde_json::from_value(call) {
+            Ok(request) => request,
+            Err(err) => {
+                return future::ready(Err(GenericError::new(
+                    error_codes::BAD_REQUEST,
+                    err.to_string(),
+                )))
+                .boxed();
+            }
+        };
+
+        if checks.read_only && !request.read_only() {
+            return future::ready(Err(GenericError::new(
+                error_codes::NOT_ALLOWED,
+                format!("fn {} is not read-only", request.fn_name()),
+            )))
+            .boxed();
+        }
+
+        if let Some(requires) = request.requires()
+            && let Err(err) = checks.authorize(S::NAME, request.fn_name(), requires)
+        {
+            return future::ready(Err(err)).boxed();
+        }
+
+        Service::call(self, request)
+            .map(|response| {
+                serde_json::to_value(response)
+                    .map_err(|err| GenericError::new(error_codes::INTERNAL, err.to_string()))
+            })
+            .boxed()
+    }
+
+    fn descriptor(&self) -> ServiceDescriptor<'static> {
+        S::DESCRIPTOR
+    }
+}
+
+trait ErasedStreamService: Send + Sync {
+    fn open(
+        &self,
+        call: Value,
+        checks: &Checks<'_>,
+    ) -> Result<DispatchStream, GenericError<'static>>;
+
+    fn fns(&self) -> &'static [&'static str];
+}
+
+impl<S> ErasedStreamService for S
+where
+    S: StreamService + Send + Sync,
+    S::Request: DeserializeOwned,
+    S::Item: Serialize,
+{
+    fn open(
+        &self,
+        call: Value,
+        checks: &Checks<'_>,
+    ) -> Result<DispatchStream, GenericError<'static>> {
+        // Stream requests don't have to name their fn, so it is taken from the call for the
+        // authorizer
+        let fn_name = call
+            .get("fn")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_owned();
+        let request = serde_json::from_value(call)
+            .map_err(|err| GenericError::new(error_codes::BAD_REQUEST, err.to_string()))?;
+
+        if let Some(requires) = StreamService::requires(self, &request) {
+            checks.authorize(S::NAME, &fn_name, requires)?;
+        }
+
+        Ok(StreamService::open(self, request)
+            .map(|item| {
+                serde_json::to_value(item?)
+                    .map_err(|err| GenericError::new(error_codes::INTERNAL, err.to_string()))
+            })
+            .boxed())
+    }
+
+    fn fns(&self) -> &'static [&'static str] {
+        S::FNS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use futures::{executor::block_on, stream};
+    use serde::Deserialize;
+    use serde_json::json;
+
+    use super::*;
+    use crate::metrics::{CallMetrics, MetricsRecorder};
+
+    struct Echo;
+
+    #[derive(Deserialize)]
+    #[serde(tag = "fn", content = "args")]
+    enum EchoRequest {
+        Echo {
+            #[serde(rename = "0")]
+            value: String,
+        },
+    }
+
+    impl ServiceRequest for EchoRequest {
+        fn fn_name(&self) -> &'static str {
+            "Echo"
+        }
+
+        fn read_only(&self) -> bool {
+            true
+        }
+    }
+
+    impl Service for Echo {
+        const NAME: &'static str = "Echo";
+        type Request = EchoRequest;
+        type Response = String;
+
+        fn call(&self, request: Self::Request) -> impl Future<Output = Self::Response> + Send {
+            let EchoRequest::Echo { value } = request;
+            future::ready(value)
+        }
+    }
+
+    /// A stream that stays open until it is dropped.
+    struct Forever;
+
+    impl StreamService for Forever {
+        const NAME: &'static str = "Forever";
+        const FNS: &'static [&'static str] = &["Forever"];
+        type Request = Value;
+        type Item = ();
+
+        fn open(
+            &self,
+            _: Self::Request,
+        ) -> impl Stream<Item = Result<Self::Item, GenericError<'static>>> + Send + 'static
+        {
+            stream::pending()
+        }
+    }
+
+    fn dispatcher() -> Dispatcher {
+        Dispatcher::new()
+            .with_service(Echo)
+            .with_stream_service(Forever)
+    }
+
+    fn echo() -> CallResponseRequest<'static, Value> {
+        CallResponseRequest {
+            service: "Echo".into(),
+            call: json!({ "fn": "Echo", "args": { "0": "hi" } }),
+        }
+    }
+
+    fn forever() -> CallResponseRequest<'static, Value> {
+        CallResponseRequest {
+            service: "Forever".into(),
+            call: json!({ "fn": "Forever", "args": {} }),
+        }
+    }
+
+    /// Keeps the labels of every call that finishes.
+    #[derive(Clone, Default)]
+    struct Labels(Arc<Mutex<Vec<(String, String)>>>);
+
+    impl MetricsRecorder for Labels {
+        fn call_started(&self, _: Side, _: &str, _: &str) {}
+
+        fn call_finished(&self, call: &CallMetrics<'_>) {
+            self.0
+                .lock()
+                .unwrap()
+                .push((call.service.to_owned(), call.fn_name.to_owned()));
+        }
+    }
+
+    fn connection(dispatcher: &Dispatcher) -> Extensions {
+        let mut extensions = Extensions::new();
+        extensions.insert(dispatcher.connection_limit());
+        extensions
+    }
+
+    fn error_code<T>(result: Result<T, GenericError<'static>>) -> Option<Cow<'static, str>> {
+        result.err().map(|err| err.code)
+    }
+
+    #[test]
+    fn calls_over_the_concurrency_limit_are_overloaded() {
+        let dispatcher = dispatcher().with_max_concurrency(1);
+
+        let stream = dispatcher.dispatch_stream(forever()).unwrap();
+        assert_eq!(
+            error_code(block_on(dispatcher.dispatch(echo()))).as_deref(),
+            Some(error_codes::OVERLOADED),
+        );
+        assert_eq!(
+            error_code(dispatcher.dispatch_stream(forever())).as_deref(),
+            Some(error_codes::OVERLOADED),
+        );
+
+        // The stream holds its slot until it is dropped
+        drop(stream);
+        assert_eq!(block_on(dispatcher.dispatch(echo())), Ok(json!("hi")));
+    }
+
+    #[test]
+    fn calls_over_a_rate_limit_are_overloaded() {
+        let dispatcher = dispatcher()
+            .with_rate_limit("Echo", RateLimit::per_second(1))
+            .with_fn_rate_limit("Forever", "Forever", RateLimit::per_second(1));
+
+        assert!(block_on(dispatcher.dispatch(echo())).is_ok());
+        assert_eq!(
+            error_code(block_on(dispatcher.dispatch(echo()))).as_deref(),
+            Some(error_codes::OVERLOADED),
+        );
+
+        assert!(dispatcher.dispatch_stream(forever()).is_ok());
+        assert_eq!(
+            error_code(dispatcher.dispatch_stream(forever())).as_deref(),
+            Some(error_codes::OVERLOADED),
+        );
+    }
+
+    #[test]
+    fn connections_at_their_limit_do_not_starve_others() {
+        let dispatcher = dispatcher()
+            .with_max_concurrency(4)
+            .with_max_in_flight_per_connection(1);
+        let first = connection(&dispatcher);
+        let second = connection(&dispatcher);
+
+        let _stream = dispatcher.dispatch_stream_with(forever(), &first).unwrap();
+        assert_eq!(
+            error_code(block_on(dispatcher.dispatch_with(echo(), &first))).as_deref(),
+            Some(error_codes::OVERLOADED),
+        );
+        assert_eq!(
+            error_code(dispatcher.dispatch_stream_with(forever(), &first)).as_deref(),
+            Some(error_codes::OVERLOADED),
+        );
+
+        assert_eq!(
+            block_on(dispatcher.dispatch_with(echo(), &second)),
+            Ok(json!("hi")),
+        );
+        assert!(dispatcher.dispatch_stream_with(forever(), &second).is_ok());
+    }
+
+    #[test]
+    fn batches_count_as_one_call() {
+        let dispatcher = dispatcher().with_max_concurrency(1);
+
+        let results = block_on(dispatcher.dispatch_batch(vec![echo(), echo(), echo()]));
+        assert!(
+            results
+                .iter()
+                .all(|result| matches!(result, BatchResult::Data(_))),
+        );
+
+        let _stream = dispatcher.dispatch_stream(forever()).unwrap();
+        let results = block_on(dispatcher.dispatch_batch(vec![echo(), echo()]));
+        assert!(results.iter().all(
+            |result| matches!(result, BatchResult::Error(err) if err.code == error_codes::OVERLOADED)
+        ));
+    }
+
+    #[test]
+    fn streams_are_reported_under_known_fns() {
+        let labels = Labels::default();
+        let dispatcher = dispatcher().with_metrics(Metrics::new(labels.clone()));
+
+        let _stream = dispatcher.dispatch_stream(forever()).unwrap();
+        let _ = dispatcher.dispatch_stream(CallResponseRequest {
+            service: "Forever".into(),
+            call: json!({ "fn": "Other", "args": {} }),
+        });
+        let _ = dispatcher.dispatch_stream(CallResponseRequest {
+            service: "Missing".into(),
+            call: json!({ "fn": "Forever", "args": {} }),
+        });
+
+        let unknown = UNKNOWN_LABEL.to_owned();
+        assert_eq!(
+            *labels.0.lock().unwrap(),
+            [
+                ("Forever".to_owned(), "Forever".to_owned()),
+                ("Forever".to_owned(), unknown.clone()),
+                (unknown.clone(), unknown),
+            ],
+        );
+    }
+}
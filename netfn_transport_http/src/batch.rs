@@ -0,0 +1,190 @@
+use std::{fmt, marker::PhantomData};
+
+use netfn_core::{
+    BatchResult, CallResponseRequest,
+    metrics::{Outcome, PayloadSizes, Side},
+};
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::Value;
+
+use crate::{HttpTransport, TransportError};
+
+/// A set of calls, possibly across services, that are sent in a single request.
+///
+/// Each queued call returns a [`BatchCall`] handle that is used to fetch its typed result
+/// from the [`BatchResponse`] once the batch has been sent.
+#[derive(Debug)]
+pub struct Batch<'t> {
+    transport: &'t HttpTransport,
+    calls: Vec<CallResponseRequest<'static, Value>>,
+}
+
+impl<'t> Batch<'t> {
+    pub(crate) fn new(transport: &'t HttpTransport) -> Self {
+        Self {
+            transport,
+            calls: Vec::new(),
+        }
+    }
+
+    #[allow(clippy::missing_errors_doc)]
+    pub fn call<Req, Res>(
+        &mut self,
+        service: &'static str,
+        request: Req,
+    ) -> Result<BatchCall<Res>, TransportError>
+    where
+        Req: Serialize,
+        Res: DeserializeOwned,
+    {
+        let index = self.calls.len();
+        self.calls.push(CallResponseRequest {
+            service: service.into(),
+            call: serde_json::to_value(request)?,
+        });
+
+        Ok(BatchCall {
+            index,
+            _res: PhantomData,
+        })
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.calls.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.calls.is_empty()
+    }
+
+    /// Sends the batch, which is reported to the transport's metrics as one call per entry.
+    ///
+    /// The payload sizes of each entry are those of its own JSON encoding, without the array
+    /// around it or the compression of the whole batch.
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn send(self) -> Result<BatchResponse, TransportError> {
+        if self.calls.is_empty() {
+            return Ok(BatchResponse {
+                results: Vec::new(),
+            });
+        }
+
+        let timers = match &self.transport.metrics {
+            Some(metrics) => self
+                .calls
+                .iter()
+                .map(|call| {
+                    let fn_name = call.call.get("fn").and_then(Value::as_str);
+                    let timer = metrics.start(
+                        Side::Client,
+                        call.service.clone(),
+                        fn_name.unwrap_or_default().to_owned(),
+                    );
+                    (timer, json_size(call))
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+
+        let result = self.send_inner().await;
+        for (index, (timer, request)) in timers.into_iter().enumerate() {
+            let (outcome, response) = match &result {
+                Ok(results) => match &results[index] {
+                    result @ BatchResult::Data(_) => (Outcome::Ok, json_size(result)),
+                    result @ BatchResult::Error(err) => {
+                        (Outcome::Error { code: &err.code }, json_size(result))
+                    }
+                },
+                Err(TransportError::Handler(err)) => (Outcome::Error { code: &err.code }, None),
+                Err(_) => (Outcome::Failed, None),
+            };
+            timer.finish(outcome, PayloadSizes { request, response });
+        }
+
+        Ok(BatchResponse { results: result? })
+    }
+
+    async fn send_inner(&self) -> Result<Vec<BatchResult<'static, Value>>, TransportError> {
+        let results: Vec<BatchResult<'static, Value>> = self.transport.post(&self.calls).await?;
+        if results.len() != self.calls.len() {
+            return Err(TransportError::BatchLength {
+                expected: self.calls.len(),
+                actual: results.len(),
+            });
+        }
+
+        Ok(results)
+    }
+}
+
+fn json_size<T: Serialize>(value: &T) -> Option<usize> {
+    serde_json::to_vec(value).ok().map(|json| json.len())
+}
+
+/// Handle to a call queued in a [`Batch`].
+pub struct BatchCall<Res> {
+    index: usize,
+    _res: PhantomData<fn() -> Res>,
+}
+
+impl<Res> BatchCall<Res> {
+    #[must_use]
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+impl<Res> Clone for BatchCall<Res> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<Res> Copy for BatchCall<Res> {}
+
+impl<Res> fmt::Debug for BatchCall<Res> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BatchCall")
+            .field("index", &self.index)
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+pub struct BatchResponse {
+    results: Vec<BatchResult<'static, Value>>,
+}
+
+impl BatchResponse {
+    /// Decodes the result of a single call in the batch.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TransportError::Handler`] if the call failed on the server, or
+    /// [`TransportError::Json`] if the result could not be decoded.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `call` was queued on a different batch with more calls than this one.
+    pub fn get<Res>(&self, call: BatchCall<Res>) -> Result<Res, TransportError>
+    where
+        Res: DeserializeOwned,
+    {
+        match &self.results[call.index] {
+            BatchResult::Data(data) => Ok(Res::deserialize(data)?),
+            BatchResult::Error(err) => Err(err.clone().into()),
+        }
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.results.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.results.is_empty()
+    }
+}
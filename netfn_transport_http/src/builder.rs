@@ -0,0 +1,163 @@
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Duration;
+
+use netfn_core::metrics::Metrics;
+use reqwest::{
+    Client, StatusCode,
+    header::{HeaderMap, HeaderName, HeaderValue, USER_AGENT},
+};
+use url::Url;
+
+use crate::{
+    ContentEncoding, Error, HttpTransport, RequestCompression, Routing, handler_error_status,
+};
+
+/// Configuration for a [`HttpTransport`], created with [`HttpTransport::builder`].
+#[derive(Debug, Clone)]
+pub struct HttpTransportBuilder {
+    client: Option<Client>,
+    headers: HeaderMap,
+    #[cfg(not(target_arch = "wasm32"))]
+    timeout: Option<Duration>,
+    routing: Routing,
+    get_read_only: bool,
+    compression: Option<RequestCompression>,
+    handler_error_status: StatusCode,
+    max_response_size: Option<usize>,
+    metrics: Option<Metrics>,
+}
+
+impl Default for HttpTransportBuilder {
+    fn default() -> Self {
+        Self {
+            client: None,
+            headers: HeaderMap::new(),
+            #[cfg(not(target_arch = "wasm32"))]
+            timeout: None,
+            routing: Routing::default(),
+            get_read_only: false,
+            compression: None,
+            handler_error_status: handler_error_status(),
+            max_response_size: None,
+            metrics: None,
+        }
+    }
+}
+
+impl HttpTransportBuilder {
+    /// Sets the client used to make requests, otherwise a default client is used.
+    #[must_use]
+    pub fn client(mut self, client: Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Adds a header that is sent with every request.
+    #[must_use]
+    pub fn header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Adds headers that are sent with every request.
+    #[must_use]
+    pub fn headers(mut self, headers: HeaderMap) -> Self {
+        self.headers.extend(headers);
+        self
+    }
+
+    #[must_use]
+    pub fn user_agent(self, value: HeaderValue) -> Self {
+        self.header(USER_AGENT, value)
+    }
+
+    /// Sets the timeout for each call, from sending the request until the response has been
+    /// read.
+    #[cfg(not(target_arch = "wasm32"))]
+    #[must_use]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    #[must_use]
+    pub fn routing(mut self, routing: Routing) -> Self {
+        self.routing = routing;
+        self
+    }
+
+    /// Sends calls to fns marked with `#[netfn(read_only)]` as `GET` requests, which allows
+    /// their responses to be cached by browsers and CDNs.
+    #[must_use]
+    pub fn get_read_only(mut self, enabled: bool) -> Self {
+        self.get_read_only = enabled;
+        self
+    }
+
+    /// Compresses request bodies that are at least `threshold` bytes long.
+    ///
+    /// Responses are always decompressed if they use an encoding enabled for this crate.
+    #[must_use]
+    pub fn compress_requests(mut self, encoding: ContentEncoding, threshold: usize) -> Self {
+        self.compression = Some(RequestCompression {
+            encoding,
+            threshold,
+        });
+        self
+    }
+
+    /// Sets the status code that the server uses for handler errors.
+    ///
+    /// This defaults to [`HANDLER_ERROR_CODE`](crate::HANDLER_ERROR_CODE), but some proxies will
+    /// rewrite non-standard status codes, in which case the server and client both need to agree
+    /// on another code.
+    #[must_use]
+    pub fn handler_error_status(mut self, status: StatusCode) -> Self {
+        self.handler_error_status = status;
+        self
+    }
+
+    /// Sets the maximum size of a response body, in bytes.
+    ///
+    /// For streams, the limit applies to each frame rather than the whole response.
+    #[must_use]
+    pub fn max_response_size(mut self, limit: usize) -> Self {
+        self.max_response_size = Some(limit);
+        self
+    }
+
+    /// Reports every call made through the transport, including each call in a batch and the
+    /// opening of streams.
+    #[must_use]
+    pub fn metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Creates the transport for the endpoint at `url`, which must end with a `/`.
+    #[allow(clippy::missing_errors_doc)]
+    pub fn build<U, E>(self, url: U) -> Result<HttpTransport, Error<E>>
+    where
+        U: TryInto<Url, Error = E>,
+    {
+        let url: Url = url.try_into()?;
+
+        if url.cannot_be_a_base() || !url.path().ends_with('/') {
+            return Err(Error::InvalidUrl(url));
+        }
+
+        Ok(HttpTransport {
+            url,
+            client: self.client.unwrap_or_default(),
+            headers: self.headers,
+            #[cfg(not(target_arch = "wasm32"))]
+            timeout: self.timeout,
+            routing: self.routing,
+            get_read_only: self.get_read_only,
+            compression: self.compression,
+            handler_error_status: self.handler_error_status,
+            max_response_size: self.max_response_size,
+            metrics: self.metrics,
+        })
+    }
+}
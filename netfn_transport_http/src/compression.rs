@@ -0,0 +1,141 @@
+use std::io;
+
+/// Encodings that request bodies can be compressed with.
+///
+/// Each encoding is enabled by the crate feature of the same name, which also enables
+/// decompressing responses that use it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentEncoding {
+    #[cfg(feature = "brotli")]
+    Brotli,
+    #[cfg(feature = "gzip")]
+    Gzip,
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl ContentEncoding {
+    /// The name used for this encoding in the `Content-Encoding` header.
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            #[cfg(feature = "brotli")]
+            Self::Brotli => "br",
+            #[cfg(feature = "gzip")]
+            Self::Gzip => "gzip",
+            #[cfg(feature = "zstd")]
+            Self::Zstd => "zstd",
+        }
+    }
+
+    #[allow(clippy::missing_errors_doc)]
+    #[cfg_attr(
+        not(any(feature = "brotli", feature = "gzip", feature = "zstd")),
+        allow(unused_variables)
+    )]
+    pub fn encode(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "brotli")]
+            Self::Brotli => {
+                let mut out = Vec::new();
+                {
+                    let mut encoder = brotli::CompressorWriter::new(&mut out, 4096, 5, 22);
+                    io::Write::write_all(&mut encoder, data)?;
+                }
+                Ok(out)
+            }
+            #[cfg(feature = "gzip")]
+            Self::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                io::Write::write_all(&mut encoder, data)?;
+                encoder.finish()
+            }
+            #[cfg(feature = "zstd")]
+            Self::Zstd => zstd::encode_all(data, 0),
+        }
+    }
+}
+
+/// Compression applied to request bodies that are at least `threshold` bytes long.
+///
+/// Smaller bodies are sent as-is, as compressing them tends to cost more than it saves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestCompression {
+    pub encoding: ContentEncoding,
+    pub threshold: usize,
+}
+
+impl RequestCompression {
+    pub(crate) fn apply(self, body: Vec<u8>) -> io::Result<(Option<&'static str>, Vec<u8>)> {
+        if body.len() < self.threshold {
+            return Ok((None, body));
+        }
+
+        Ok((Some(self.encoding.name()), self.encoding.encode(&body)?))
+    }
+}
+
+#[cfg(all(test, any(feature = "brotli", feature = "gzip", feature = "zstd")))]
+mod tests {
+    use std::io::Read as _;
+
+    use super::*;
+
+    const ENCODINGS: &[ContentEncoding] = &[
+        #[cfg(feature = "brotli")]
+        ContentEncoding::Brotli,
+        #[cfg(feature = "gzip")]
+        ContentEncoding::Gzip,
+        #[cfg(feature = "zstd")]
+        ContentEncoding::Zstd,
+    ];
+
+    fn decode(encoding: ContentEncoding, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        match encoding {
+            #[cfg(feature = "brotli")]
+            ContentEncoding::Brotli => brotli::Decompressor::new(data, 4096)
+                .read_to_end(&mut out)
+                .unwrap(),
+            #[cfg(feature = "gzip")]
+            ContentEncoding::Gzip => flate2::read::GzDecoder::new(data)
+                .read_to_end(&mut out)
+                .unwrap(),
+            #[cfg(feature = "zstd")]
+            ContentEncoding::Zstd => zstd::Decoder::new(data)
+                .unwrap()
+                .read_to_end(&mut out)
+                .unwrap(),
+        };
+        out
+    }
+
+    #[test]
+    fn bodies_under_the_threshold_are_sent_as_is() {
+        for &encoding in ENCODINGS {
+            let compression = RequestCompression {
+                encoding,
+                threshold: 16,
+            };
+            let body = b"{\"0\":\"short\"}".to_vec();
+
+            assert_eq!(compression.apply(body.clone()).unwrap(), (None, body));
+        }
+    }
+
+    #[test]
+    fn bodies_over_the_threshold_are_compressed() {
+        for &encoding in ENCODINGS {
+            let compression = RequestCompression {
+                encoding,
+                threshold: 16,
+            };
+            let body = b"{\"0\":\"long enough\"}".to_vec();
+
+            let (name, compressed) = compression.apply(body.clone()).unwrap();
+            assert_eq!(name, Some(encoding.name()));
+            assert_eq!(decode(encoding, &compressed), body);
+        }
+    }
+}
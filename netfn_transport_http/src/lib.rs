@@ -1,34 +1,289 @@
 #![warn(clippy::pedantic)]
 
+mod batch;
+mod builder;
+mod compression;
+#[cfg(feature = "openapi")]
+pub mod openapi;
+#[cfg(feature = "server")]
+pub mod server;
+mod stream;
+
 use std::convert::Infallible;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Duration;
 
-use netfn_core::{CallResponseRequest, GenericError, Transport};
-use reqwest::Client;
+pub use batch::*;
+pub use builder::*;
+pub use compression::*;
+use netfn_core::{
+    CallResponseRequest, GenericError, ServiceRequest, Transport,
+    metrics::{Metrics, Outcome, PayloadSizes, Side},
+};
+pub use reqwest;
+use reqwest::{
+    Client, Method, RequestBuilder, Response, StatusCode,
+    header::{CONTENT_ENCODING, CONTENT_TYPE, HeaderMap, HeaderValue},
+};
 use serde::{Serialize, de::DeserializeOwned};
+use serde_json::{Map, Value};
+pub use stream::*;
 use thiserror::Error;
 use url::{ParseError, Url};
 
-const HANDLER_ERROR_CODE: u16 = 537;
+/// Status code that a server uses to say that a `GenericError` has been sent.
+pub const HANDLER_ERROR_CODE: u16 = 537;
+/// How much of the body is kept for failed responses that aren't handler errors.
+const STATUS_BODY_LIMIT: usize = 4096;
 
 #[derive(Debug, Clone)]
 pub struct HttpTransport {
     url: Url,
     client: Client,
+    headers: HeaderMap,
+    #[cfg(not(target_arch = "wasm32"))]
+    timeout: Option<Duration>,
+    routing: Routing,
+    get_read_only: bool,
+    compression: Option<RequestCompression>,
+    handler_error_status: StatusCode,
+    max_response_size: Option<usize>,
+    metrics: Option<Metrics>,
+}
+
+/// Where the service and fn of a call are put in requests.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Routing {
+    /// Calls are sent to the base url, with the service and fn in the body.
+    #[default]
+    Body,
+    /// Calls are sent to `{base}/{service}/{fn}`, with only the args in the body.
+    ///
+    /// This makes calls visible to anything that works on paths, such as access logs,
+    /// reverse-proxies and rate limiters.
+    Path,
 }
 
 impl HttpTransport {
-    #[must_use]
+    #[allow(clippy::missing_errors_doc)]
     pub fn new<U, E>(url: U, client: Client) -> Result<Self, Error<E>>
     where
         U: TryInto<Url, Error = E>,
     {
-        let url: Url = url.try_into()?;
+        Self::builder().client(client).build(url)
+    }
+
+    #[must_use]
+    pub fn builder() -> HttpTransportBuilder {
+        HttpTransportBuilder::default()
+    }
+
+    /// Starts a batch of calls that will be sent together in a single request.
+    #[must_use]
+    pub fn batch(&self) -> Batch<'_> {
+        Batch::new(self)
+    }
+
+    fn fn_url(&self, service: &str, fn_name: &str) -> Url {
+        let mut url = self.url.clone();
+        url.path_segments_mut()
+            .expect("base url is checked on creation")
+            .pop_if_empty()
+            .push(service)
+            .push(fn_name);
+        url
+    }
+
+    /// Starts a request with the headers and timeout that apply to every call.
+    fn request(&self, method: Method, url: Url) -> RequestBuilder {
+        let request = self
+            .client
+            .request(method, url)
+            .headers(self.headers.clone());
+
+        #[cfg(not(target_arch = "wasm32"))]
+        let request = match self.timeout {
+            Some(timeout) => request.timeout(timeout),
+            None => request,
+        };
+
+        request
+    }
+
+    async fn post<Req, Res>(&self, body: Req) -> Result<Res, TransportError>
+    where
+        Req: Serialize,
+        Res: DeserializeOwned,
+    {
+        let request = self.json_body(self.request(Method::POST, self.url.clone()), &body)?;
+        self.send(request).await
+    }
 
-        if url.cannot_be_a_base() || !url.path().ends_with("/") {
-            Err(Error::InvalidUrl(url))
+    async fn call_inner<Req, Res>(
+        &self,
+        service: &str,
+        request: Req,
+        sizes: &mut PayloadSizes,
+    ) -> Result<Res, TransportError>
+    where
+        Req: Serialize + ServiceRequest,
+        Res: DeserializeOwned,
+    {
+        let (request, size) = if self.get_read_only && request.read_only() {
+            self.get_call(service, &request)?
         } else {
-            Ok(Self { url, client })
+            self.post_call(service, &request)?
+        };
+        sizes.request = Some(size);
+
+        let body = self.read_body(self.execute(request).await?).await?;
+        sizes.response = Some(body.len());
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Builds the `POST` request for a call, following the configured routing, along with the
+    /// size of its body.
+    fn post_call<Req>(
+        &self,
+        service: &str,
+        request: &Req,
+    ) -> Result<(RequestBuilder, usize), TransportError>
+    where
+        Req: Serialize + ServiceRequest,
+    {
+        let (url, body) = match self.routing {
+            Routing::Body => (
+                self.url.clone(),
+                serde_json::to_vec(&CallResponseRequest {
+                    service: service.into(),
+                    call: request,
+                })?,
+            ),
+            Routing::Path => {
+                let args = call_args(request)?.unwrap_or_else(|| Value::Object(Map::new()));
+                (
+                    self.fn_url(service, request.fn_name()),
+                    serde_json::to_vec(&args)?,
+                )
+            }
+        };
+
+        let size = body.len();
+        Ok((
+            self.encoded_body(self.request(Method::POST, url), body)?,
+            size,
+        ))
+    }
+
+    /// Sets the body of a request to JSON, compressing it if configured to.
+    fn json_body<T>(
+        &self,
+        request: RequestBuilder,
+        body: &T,
+    ) -> Result<RequestBuilder, TransportError>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.encoded_body(request, serde_json::to_vec(body)?)
+    }
+
+    fn encoded_body(
+        &self,
+        request: RequestBuilder,
+        body: Vec<u8>,
+    ) -> Result<RequestBuilder, TransportError> {
+        let request = request.header(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+
+        let Some(compression) = self.compression else {
+            return Ok(request.body(body));
+        };
+
+        Ok(
+            match compression.apply(body).map_err(TransportError::Compress)? {
+                (Some(encoding), body) => request
+                    .header(CONTENT_ENCODING, HeaderValue::from_static(encoding))
+                    .body(body),
+                (None, body) => request.body(body),
+            },
+        )
+    }
+
+    /// Builds the `GET` request for a read-only call, along with the size of its args.
+    fn get_call<Req>(
+        &self,
+        service: &str,
+        request: &Req,
+    ) -> Result<(RequestBuilder, usize), TransportError>
+    where
+        Req: Serialize + ServiceRequest,
+    {
+        let mut url = self.fn_url(service, request.fn_name());
+
+        // Empty args are left off entirely to keep the url stable for caches.
+        let mut size = 0;
+        let args = call_args(request)?;
+        if let Some(args) = args.filter(|args| args.as_object().is_none_or(|args| !args.is_empty()))
+        {
+            let args = args.to_string();
+            size = args.len();
+            url.query_pairs_mut().append_pair("args", &args);
+        }
+
+        Ok((self.request(Method::GET, url), size))
+    }
+
+    async fn send<Res>(&self, request: RequestBuilder) -> Result<Res, TransportError>
+    where
+        Res: DeserializeOwned,
+    {
+        let response = self.execute(request).await?;
+        let body = self.read_body(response).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Sends a request, turning handler errors into [`TransportError::Handler`].
+    async fn execute(&self, request: RequestBuilder) -> Result<Response, TransportError> {
+        let response = request.send().await?;
+        let status = response.status();
+
+        if status == self.handler_error_status {
+            let body = self.read_body(response).await?;
+            let err: GenericError<'static> = serde_json::from_slice(&body)?;
+            return Err(err.into());
+        }
+
+        if !status.is_success() {
+            return Err(TransportError::Status {
+                status,
+                body: read_truncated(response, STATUS_BODY_LIMIT).await?,
+            });
+        }
+
+        Ok(response)
+    }
+
+    /// Reads the whole response body, failing as soon as it goes over the size limit.
+    async fn read_body(&self, mut response: Response) -> Result<Vec<u8>, TransportError> {
+        let Some(limit) = self.max_response_size else {
+            return Ok(response.bytes().await?.into());
+        };
+
+        if response
+            .content_length()
+            .is_some_and(|len| len > limit as u64)
+        {
+            return Err(TransportError::TooLarge { limit });
         }
+
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            if body.len() + chunk.len() > limit {
+                return Err(TransportError::TooLarge { limit });
+            }
+            body.extend_from_slice(&chunk);
+        }
+
+        Ok(body)
     }
 }
 
@@ -61,26 +316,67 @@ impl Transport for HttpTransport {
 
     async fn call<Req, Res>(&self, service: &'static str, request: Req) -> Result<Res, Self::Error>
     where
-        Req: netfn_core::compat::NetfnSend + Serialize,
+        Req: netfn_core::compat::NetfnSend + Serialize + ServiceRequest,
         Res: netfn_core::compat::NetfnSend + DeserializeOwned,
     {
-        let response = self
-            .client
-            .post(self.url.clone())
-            .json(&CallResponseRequest {
-                service: service.into(),
-                call: request,
-            })
-            .send()
-            .await?;
-
-        if response.status().as_u16() == HANDLER_ERROR_CODE {
-            let err: GenericError<'static> = response.json().await?;
-            return Err(err.into());
-        }
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!("netfn.call", service, fn_name = request.fn_name());
+
+        let timer = self
+            .metrics
+            .as_ref()
+            .map(|metrics| metrics.start(Side::Client, service, request.fn_name()));
+
+        let call = async {
+            let mut sizes = PayloadSizes::default();
+            let result = self.call_inner(service, request, &mut sizes).await;
+            if let Some(timer) = timer {
+                let outcome = match &result {
+                    Ok(_) => Outcome::Ok,
+                    Err(TransportError::Handler(err)) => Outcome::Error { code: &err.code },
+                    Err(_) => Outcome::Failed,
+                };
+                timer.finish(outcome, sizes);
+            }
+            #[cfg(feature = "tracing")]
+            if let Err(err) = &result {
+                tracing::debug!(error = %err, "call failed");
+            }
+            result
+        };
+
+        #[cfg(feature = "tracing")]
+        let call = tracing::Instrument::instrument(call, span);
+        call.await
+    }
+}
+
+fn handler_error_status() -> StatusCode {
+    StatusCode::from_u16(HANDLER_ERROR_CODE).expect("handler error code should be a valid status")
+}
 
-        Ok(response.json().await?)
+/// Reads the response body as text, stopping once `limit` bytes have been read.
+async fn read_truncated(mut response: Response, limit: usize) -> Result<String, TransportError> {
+    let mut body = Vec::new();
+    while body.len() < limit {
+        let Some(chunk) = response.chunk().await? else {
+            break;
+        };
+        body.extend_from_slice(&chunk[..chunk.len().min(limit - body.len())]);
     }
+
+    Ok(String::from_utf8_lossy(&body).into_owned())
+}
+
+/// Pulls the args out of a call for when the fn is already in the path.
+fn call_args<Req>(request: &Req) -> Result<Option<Value>, serde_json::Error>
+where
+    Req: Serialize,
+{
+    Ok(match serde_json::to_value(request)? {
+        Value::Object(mut call) => call.remove("args"),
+        _ => None,
+    })
 }
 
 #[derive(Error, Debug)]
@@ -89,4 +385,30 @@ pub enum TransportError {
     Request(#[from] reqwest::Error),
     #[error("{0}")]
     Handler(#[from] netfn_core::GenericError<'static>),
+    #[error("request failed with status {status}")]
+    Status {
+        status: reqwest::StatusCode,
+        body: String,
+    },
+    #[error("failed to convert call: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("response is larger than the limit of {limit} bytes")]
+    TooLarge { limit: usize },
+    #[error("failed to compress request: {0}")]
+    Compress(#[source] std::io::Error),
+    #[error("batch of {expected} calls received {actual} results")]
+    BatchLength { expected: usize, actual: usize },
+    /// The response to a stream ended without the server closing the stream, such as when the
+    /// connection was cut.
+    #[error("the stream ended before it was closed")]
+    UnexpectedEnd,
+}
+
+impl netfn_core::HandlerError for TransportError {
+    fn handler_error(&self) -> Option<&netfn_core::GenericError<'static>> {
+        match self {
+            Self::Handler(err) => Some(err),
+            _ => None,
+        }
+    }
 }
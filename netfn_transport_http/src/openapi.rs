@@ -0,0 +1,241 @@
+//! `OpenAPI` 3.1 documents for services served with [`HttpServer`](crate::server::HttpServer).
+//!
+//! Services need to be generated with `#[netfn::service(schema)]` to be documented.
+
+use netfn_core::{
+    schema::ServiceSchema,
+    schemars::{SchemaGenerator, generate::SchemaSettings},
+};
+use reqwest::StatusCode;
+use serde_json::{Map, Value, json};
+
+use crate::handler_error_status;
+
+/// Builds an `OpenAPI` document for the call-response interface of a set of services.
+///
+/// Every fn gets its own operation using [path routing](crate::Routing::Path), with read-only
+/// fns also getting a `GET` operation. The endpoint itself is documented as a single operation
+/// that takes a call to any of the services, or a batch of them.
+#[derive(Debug)]
+pub struct OpenApi {
+    title: String,
+    version: String,
+    servers: Vec<String>,
+    handler_error_status: StatusCode,
+    generator: SchemaGenerator,
+    calls: Vec<Value>,
+    returns: Vec<Value>,
+    paths: Map<String, Value>,
+}
+
+impl OpenApi {
+    #[must_use]
+    pub fn new(title: impl Into<String>, version: impl Into<String>) -> Self {
+        let mut settings = SchemaSettings::draft2020_12();
+        settings.definitions_path = "/components/schemas".into();
+        settings.meta_schema = None;
+
+        Self {
+            title: title.into(),
+            version: version.into(),
+            servers: Vec::new(),
+            handler_error_status: handler_error_status(),
+            generator: settings.into_generator(),
+            calls: Vec::new(),
+            returns: Vec::new(),
+            paths: Map::new(),
+        }
+    }
+
+    /// Adds the url of an endpoint that serves the services.
+    #[must_use]
+    pub fn server(mut self, url: impl Into<String>) -> Self {
+        let mut url = url.into();
+        // Paths are appended to the server url, and they already start with a `/`.
+        if url.ends_with('/') {
+            url.pop();
+        }
+        self.servers.push(url);
+        self
+    }
+
+    /// Sets the status code used for handler errors, which must match the one set on the server.
+    #[must_use]
+    pub fn handler_error_status(mut self, status: StatusCode) -> Self {
+        self.handler_error_status = status;
+        self
+    }
+
+    /// Adds a service using its generated request enum, such as `my_api::MyApiRequest`.
+    #[must_use]
+    pub fn with_service<R>(mut self) -> Self
+    where
+        R: ServiceSchema,
+    {
+        let descriptor = R::DESCRIPTOR;
+        let service = &*descriptor.name;
+
+        let call = self.generator.subschema_for::<R>();
+        self.calls.push(json!({
+            "type": "object",
+            "properties": {
+                "service": { "const": service },
+                "call": call,
+            },
+            "required": ["service", "call"],
+        }));
+
+        for tfn in R::fn_schemas(&mut self.generator) {
+            let desc = descriptor.get(tfn.name);
+            let docs = desc.map(|desc| &*desc.docs).unwrap_or_default();
+            let read_only = desc.is_some_and(|desc| desc.read_only);
+            let returns = tfn.returns.to_value();
+
+            let mut operation = Map::from_iter([
+                (
+                    "operationId".to_owned(),
+                    format!("{service}.{}", tfn.name).into(),
+                ),
+                ("tags".to_owned(), json!([service])),
+                ("responses".to_owned(), self.responses(&returns)),
+            ]);
+            if let Some(summary) = docs.lines().next().filter(|line| !line.is_empty()) {
+                operation.insert("summary".to_owned(), summary.into());
+                operation.insert("description".to_owned(), docs.into());
+            }
+
+            let mut item = Map::new();
+            if read_only {
+                let mut get = operation.clone();
+                get.insert(
+                    "operationId".to_owned(),
+                    format!("{service}.{}.get", tfn.name).into(),
+                );
+                get.insert(
+                    "parameters".to_owned(),
+                    json!([{
+                        "name": "args",
+                        "in": "query",
+                        "required": false,
+                        "content": { "application/json": { "schema": tfn.args } },
+                    }]),
+                );
+                item.insert("get".to_owned(), Value::Object(get));
+            }
+            operation.insert(
+                "requestBody".to_owned(),
+                json!({
+                    "required": true,
+                    "content": { "application/json": { "schema": tfn.args } },
+                }),
+            );
+            item.insert("post".to_owned(), Value::Object(operation));
+
+            self.returns.push(returns);
+            self.paths
+                .insert(format!("/{service}/{}", tfn.name), Value::Object(item));
+        }
+
+        self
+    }
+
+    /// Generates the document.
+    #[must_use]
+    pub fn document(mut self) -> Value {
+        let call = json!({ "oneOf": self.calls });
+        let returns = if self.returns.is_empty() {
+            json!({})
+        } else {
+            json!({ "anyOf": self.returns })
+        };
+        let batch_result = json!({
+            "oneOf": [
+                {
+                    "type": "object",
+                    "properties": { "data": returns },
+                    "required": ["data"],
+                },
+                {
+                    "type": "object",
+                    "properties": { "error": { "$ref": "#/components/schemas/GenericError" } },
+                    "required": ["error"],
+                },
+            ],
+        });
+
+        let root = json!({
+            "operationId": "call",
+            "summary": "Calls a fn on any service, or a batch of fns",
+            "requestBody": {
+                "required": true,
+                "content": {
+                    "application/json": {
+                        "schema": { "oneOf": [call, { "type": "array", "items": call }] },
+                    },
+                },
+            },
+            // Fns may return arrays too, so a batch response can match both
+            "responses": self.responses(&json!({
+                "anyOf": [returns, { "type": "array", "items": batch_result }],
+            })),
+        });
+        self.paths.insert("/".to_owned(), json!({ "post": root }));
+
+        let mut schemas = self.generator.take_definitions(true);
+        schemas.insert(
+            "GenericError".to_owned(),
+            json!({
+                "type": "object",
+                "properties": {
+                    "code": { "type": "string" },
+                    "message": { "type": "string" },
+                },
+                "required": ["code", "message"],
+            }),
+        );
+
+        let mut document = json!({
+            "openapi": "3.1.0",
+            "info": { "title": self.title, "version": self.version },
+            "paths": self.paths,
+            "components": {
+                "schemas": schemas,
+                "responses": {
+                    "HandlerError": {
+                        "description": "The call failed before or while the handler ran",
+                        "content": {
+                            "application/json": {
+                                "schema": { "$ref": "#/components/schemas/GenericError" },
+                            },
+                        },
+                    },
+                },
+            },
+        });
+        if !self.servers.is_empty() {
+            document["servers"] = self
+                .servers
+                .iter()
+                .map(|url| json!({ "url": url }))
+                .collect();
+        }
+
+        document
+    }
+
+    fn responses(&self, data: &Value) -> Value {
+        let mut responses = Map::new();
+        responses.insert(
+            "200".to_owned(),
+            json!({
+                "description": "The value returned by the fn",
+                "content": { "application/json": { "schema": data } },
+            }),
+        );
+        responses.insert(
+            self.handler_error_status.as_u16().to_string(),
+            json!({ "$ref": "#/components/responses/HandlerError" }),
+        );
+        Value::Object(responses)
+    }
+}
@@ -0,0 +1,459 @@
+use std::{
+    convert::Infallible,
+    future::{self, Ready},
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use axum::{
+    Extension, Json, Router,
+    body::Body,
+    extract::{DefaultBodyLimit, Path, Query, State},
+    http::{Extensions, HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse as _, Response},
+    routing::{get, post},
+};
+use futures::{StreamExt as _, stream};
+use netfn_core::{
+    CallResponseRequest, GenericError, StreamFrame, error_codes,
+    server::{DispatchStream, Dispatcher},
+};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::{StreamFormat, handler_error_status};
+
+/// Builds a router that serves the call-response interface for every service in `dispatcher`.
+///
+/// The router handles calls with the service and fn in either the body or the path, so it
+/// should be nested under the endpoint that clients are configured with.
+///
+/// The extensions of each request are passed to the dispatcher's authorizer, so middleware that
+/// authenticates callers can add them there.
+pub fn router<S>(dispatcher: Dispatcher) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    HttpServer::new(dispatcher).into_router()
+}
+
+/// Configuration for the call-response router.
+#[derive(Debug)]
+pub struct HttpServer {
+    dispatcher: Dispatcher,
+    cache_control: HeaderValue,
+    handler_error_status: StatusCode,
+    max_request_size: Option<usize>,
+}
+
+impl HttpServer {
+    #[must_use]
+    pub fn new(dispatcher: Dispatcher) -> Self {
+        Self {
+            dispatcher,
+            cache_control: HeaderValue::from_static("no-cache"),
+            handler_error_status: handler_error_status(),
+            max_request_size: None,
+        }
+    }
+
+    /// Sets the status code used for handler errors, which must match the one set on clients.
+    #[must_use]
+    pub fn handler_error_status(mut self, status: StatusCode) -> Self {
+        self.handler_error_status = status;
+        self
+    }
+
+    /// Sets the `Cache-Control` header sent with `GET` responses to read-only fns.
+    ///
+    /// Defaults to `no-cache`, which allows responses to be stored but makes sure they are
+    /// revalidated using their `ETag` before being reused.
+    #[must_use]
+    pub fn cache_control(mut self, value: HeaderValue) -> Self {
+        self.cache_control = value;
+        self
+    }
+
+    /// Sets the maximum size of a request body, in bytes, replacing axum's default of 2MB.
+    ///
+    /// The limit is checked while the body is read, after it has been decompressed. Requests over
+    /// the limit are rejected with `413 Payload Too Large`.
+    #[must_use]
+    pub fn max_request_size(mut self, limit: usize) -> Self {
+        self.max_request_size = Some(limit);
+        self
+    }
+
+    pub fn into_router<S>(self) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        Self::router(Arc::new(self))
+    }
+
+    /// Creates a service for `axum::serve` that gives each connection its own
+    /// [`connection_limit`](Dispatcher::connection_limit), where `app` builds the app around the
+    /// router, such as by adding middleware and other routes.
+    ///
+    /// Routers don't know which connection a call came from, so the dispatcher's limit on the
+    /// calls in flight on each connection is only enforced when served this way.
+    pub fn into_make_service_with(
+        self,
+        app: impl FnOnce(Router) -> Router,
+    ) -> MakeConnectionService {
+        let server = Arc::new(self);
+        MakeConnectionService {
+            router: app(Self::router(server.clone())),
+            server,
+        }
+    }
+
+    fn router<S>(server: Arc<Self>) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let body_limit = server.max_request_size;
+        let router = Router::new()
+            .route("/", post(call))
+            .route("/{service}/{fn}", get(call_read_only).post(call_path))
+            .with_state(server);
+
+        let router = match body_limit {
+            Some(limit) => router.layer(DefaultBodyLimit::max(limit)),
+            None => router,
+        };
+
+        // Responses are compressed based on the `Accept-Encoding` of the request, and request
+        // bodies are decompressed based on their `Content-Encoding`. Streams are left alone, as
+        // compressing them holds back frames, and the default predicate only skips event streams.
+        #[cfg(any(feature = "brotli", feature = "gzip", feature = "zstd"))]
+        let router = {
+            use tower_http::compression::{
+                CompressionLayer, DefaultPredicate, Predicate as _, predicate::NotForContentType,
+            };
+
+            let predicate = DefaultPredicate::new().and(NotForContentType::const_new(
+                StreamFormat::NdJson.content_type(),
+            ));
+            router
+                .layer(CompressionLayer::new().compress_when(predicate))
+                .layer(tower_http::decompression::RequestDecompressionLayer::new())
+        };
+
+        router
+    }
+}
+
+/// Creates the app for each connection, made with [`HttpServer::into_make_service_with`].
+#[derive(Clone, Debug)]
+pub struct MakeConnectionService {
+    server: Arc<HttpServer>,
+    router: Router,
+}
+
+impl<T> tower_service::Service<T> for MakeConnectionService {
+    type Response = Router;
+    type Error = Infallible;
+    type Future = Ready<Result<Router, Infallible>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _: T) -> Self::Future {
+        let limit = self.server.dispatcher.connection_limit();
+        future::ready(Ok(self.router.clone().layer(Extension(limit))))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CallBody {
+    Batch(Vec<CallResponseRequest<'static, Value>>),
+    Single(CallResponseRequest<'static, Value>),
+}
+
+async fn call(
+    State(server): State<Arc<HttpServer>>,
+    headers: HeaderMap,
+    extensions: Extensions,
+    Json(body): Json<CallBody>,
+) -> Response {
+    match body {
+        CallBody::Single(request) => server.call_single(&headers, &extensions, request).await,
+        CallBody::Batch(requests) => Json(
+            server
+                .dispatcher
+                .dispatch_batch_with(requests, &extensions)
+                .await,
+        )
+        .into_response(),
+    }
+}
+
+async fn call_path(
+    State(server): State<Arc<HttpServer>>,
+    Path((service, fn_name)): Path<(String, String)>,
+    headers: HeaderMap,
+    extensions: Extensions,
+    Json(args): Json<Value>,
+) -> Response {
+    server
+        .call_single(&headers, &extensions, path_request(service, fn_name, args))
+        .await
+}
+
+impl HttpServer {
+    fn handler_error(&self, err: GenericError<'static>) -> Response {
+        (self.handler_error_status, Json(err)).into_response()
+    }
+
+    /// Dispatches a single call, opening a stream instead if the client accepts one.
+    async fn call_single(
+        &self,
+        headers: &HeaderMap,
+        extensions: &Extensions,
+        request: CallResponseRequest<'static, Value>,
+    ) -> Response {
+        let stream_format = headers
+            .get(header::ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .and_then(StreamFormat::from_accept);
+
+        if let Some(format) = stream_format {
+            return match self.dispatcher.dispatch_stream_with(request, extensions) {
+                Ok(stream) => stream_response(format, stream),
+                Err(err) => self.handler_error(err),
+            };
+        }
+
+        match self.dispatcher.dispatch_with(request, extensions).await {
+            Ok(data) => Json(data).into_response(),
+            Err(err) => self.handler_error(err),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ReadOnlyQuery {
+    args: Option<String>,
+}
+
+async fn call_read_only(
+    State(server): State<Arc<HttpServer>>,
+    Path((service, fn_name)): Path<(String, String)>,
+    Query(query): Query<ReadOnlyQuery>,
+    headers: HeaderMap,
+    extensions: Extensions,
+) -> Response {
+    let args = match query.args.as_deref().map(serde_json::from_str) {
+        Some(Ok(args)) => args,
+        Some(Err(err)) => {
+            return server.handler_error(GenericError::new(
+                error_codes::BAD_REQUEST,
+                format!("failed to parse args: {err}"),
+            ));
+        }
+        None => Value::Object(Map::new()),
+    };
+
+    let request = path_request(service, fn_name, args);
+    let data = match server
+        .dispatcher
+        .dispatch_read_only_with(request, &extensions)
+        .await
+    {
+        Ok(data) => data,
+        Err(err) => return server.handler_error(err),
+    };
+
+    let body = data.to_string();
+    let etag = etag(body.as_bytes());
+    let cache_headers = [
+        (header::ETAG, etag.clone()),
+        (header::CACHE_CONTROL, server.cache_control.clone()),
+    ];
+
+    if if_none_match(&headers, &etag) {
+        return (StatusCode::NOT_MODIFIED, cache_headers).into_response();
+    }
+
+    (
+        cache_headers,
+        [(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        )],
+        body,
+    )
+        .into_response()
+}
+
+fn path_request(
+    service: String,
+    fn_name: String,
+    args: Value,
+) -> CallResponseRequest<'static, Value> {
+    CallResponseRequest {
+        service: service.into(),
+        call: Value::Object(Map::from_iter([
+            ("fn".to_owned(), Value::String(fn_name)),
+            ("args".to_owned(), args),
+        ])),
+    }
+}
+
+fn stream_response(format: StreamFormat, stream: DispatchStream) -> Response {
+    let frames = stream::unfold(Some(stream), move |stream| async move {
+        let mut stream = stream?;
+        let (frame, stream) = match stream.next().await {
+            Some(Ok(data)) => (StreamFrame::StreamMessage { data }, Some(stream)),
+            Some(Err(error)) => (StreamFrame::StreamError { error }, None),
+            None => (StreamFrame::StreamClose, None),
+        };
+        Some((format.encode(&frame), stream))
+    })
+    // Layers such as compression may poll the body again after it has ended
+    .fuse();
+
+    (
+        [
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(format.content_type()),
+            ),
+            (header::CACHE_CONTROL, HeaderValue::from_static("no-cache")),
+        ],
+        Body::from_stream(frames),
+    )
+        .into_response()
+}
+
+/// Creates a strong `ETag` from a 64-bit FNV-1a hash of the response body.
+fn etag(body: &[u8]) -> HeaderValue {
+    let hash = body.iter().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    HeaderValue::from_str(&format!("\"{hash:016x}\"")).expect("etag should be a valid header")
+}
+
+/// Checks whether the request already has the response, using the weak comparison that RFC 9110
+/// requires for `If-None-Match`, so a `W/` tag from a cache matches our strong one.
+fn if_none_match(headers: &HeaderMap, etag: &HeaderValue) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag.as_bytes() == etag.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::to_bytes, http::Request};
+    use futures::executor::block_on;
+    use netfn_conformance::{ConformanceExt as _, ConformanceService};
+    use tower_service::Service as _;
+
+    use super::*;
+
+    fn server() -> HttpServer {
+        HttpServer::new(Dispatcher::new().with_service(ConformanceService.into_service()))
+    }
+
+    fn send(server: HttpServer, request: Request<Body>) -> (StatusCode, HeaderMap, Value) {
+        let mut router: Router = server.into_router();
+        let response = block_on(router.call(request)).unwrap();
+        let (parts, body) = response.into_parts();
+        let body = block_on(to_bytes(body, usize::MAX)).unwrap();
+        let body = serde_json::from_slice(&body).unwrap_or(Value::Null);
+        (parts.status, parts.headers, body)
+    }
+
+    fn get_echo(if_none_match: Option<&str>) -> Request<Body> {
+        let mut request = Request::get("/Conformance/Echo?args=%7B%220%22%3A%22hi%22%7D");
+        if let Some(tag) = if_none_match {
+            request = request.header(header::IF_NONE_MATCH, tag);
+        }
+        request.body(Body::empty()).unwrap()
+    }
+
+    fn echo_etag() -> String {
+        let (status, headers, body) = send(server(), get_echo(None));
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "hi");
+        headers[header::ETAG].to_str().unwrap().to_owned()
+    }
+
+    #[test]
+    fn matching_etags_are_not_modified() {
+        let etag = echo_etag();
+
+        for tag in [
+            etag.clone(),
+            format!("W/{etag}"),
+            "*".to_owned(),
+            format!("\"other\", {etag}"),
+        ] {
+            let (status, headers, _) = send(server(), get_echo(Some(&tag)));
+            assert_eq!(status, StatusCode::NOT_MODIFIED, "{tag}");
+            assert_eq!(headers[header::ETAG], etag);
+        }
+    }
+
+    #[test]
+    fn other_etags_are_sent_the_response() {
+        let other = "\"0000000000000000\"";
+        assert_ne!(echo_etag(), other);
+
+        let (status, _, body) = send(server(), get_echo(Some(other)));
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "hi");
+    }
+
+    #[test]
+    fn malformed_args_are_bad_requests() {
+        let request = Request::get("/Conformance/Echo?args=%7B")
+            .body(Body::empty())
+            .unwrap();
+
+        let (status, _, body) = send(server(), request);
+        assert_eq!(status, handler_error_status());
+        assert_eq!(body["code"], error_codes::BAD_REQUEST);
+    }
+
+    #[test]
+    fn requests_over_the_size_limit_are_rejected() {
+        let body = json_body(&"x".repeat(64));
+        let request = |body: Body| {
+            Request::post("/Conformance/Echo")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(body)
+                .unwrap()
+        };
+
+        let (status, _, _) = send(server().max_request_size(32), request(body.clone().into()));
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+
+        // Without a length, the limit is hit while reading the body
+        let chunks = stream::iter(
+            body.chunks(8)
+                .map(|chunk| Ok::<_, Infallible>(chunk.to_vec()))
+                .collect::<Vec<_>>(),
+        );
+        let (status, _, _) = send(
+            server().max_request_size(32),
+            request(Body::from_stream(chunks)),
+        );
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+
+        let (status, _, body) = send(server().max_request_size(128), request(body.into()));
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "x".repeat(64));
+    }
+
+    fn json_body(value: &str) -> Vec<u8> {
+        serde_json::to_vec(&serde_json::json!({ "0": value })).unwrap()
+    }
+}
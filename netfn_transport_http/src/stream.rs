@@ -0,0 +1,426 @@
+use std::collections::VecDeque;
+
+use futures::{Stream, StreamExt as _, stream};
+use netfn_core::{
+    ServiceRequest, StreamFrame,
+    metrics::{Outcome, PayloadSizes, Side},
+};
+use reqwest::header::{ACCEPT, HeaderValue};
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::{HttpTransport, TransportError};
+
+/// How stream frames are encoded in a streamed HTTP response.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StreamFormat {
+    /// Server-Sent Events, with each frame sent as the `data` of an event.
+    #[default]
+    EventStream,
+    /// Newline-delimited JSON, with each frame on its own line.
+    NdJson,
+}
+
+impl StreamFormat {
+    #[must_use]
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::EventStream => "text/event-stream",
+            Self::NdJson => "application/x-ndjson",
+        }
+    }
+
+    /// Finds the first stream format in an `Accept` header, if any.
+    #[must_use]
+    pub fn from_accept(accept: &str) -> Option<Self> {
+        accept
+            .split(',')
+            .filter_map(|media| media.split(';').next())
+            .find_map(|media| match media.trim() {
+                "text/event-stream" => Some(Self::EventStream),
+                "application/x-ndjson" => Some(Self::NdJson),
+                _ => None,
+            })
+    }
+
+    /// Encodes a single frame, including its delimiter.
+    #[allow(clippy::missing_errors_doc)]
+    pub fn encode<T>(self, frame: &StreamFrame<'_, T>) -> Result<Vec<u8>, serde_json::Error>
+    where
+        T: Serialize,
+    {
+        let json = serde_json::to_vec(frame)?;
+        Ok(match self {
+            Self::EventStream => [b"data: ", &json[..], b"\n\n"].concat(),
+            Self::NdJson => [&json[..], b"\n"].concat(),
+        })
+    }
+}
+
+impl HttpTransport {
+    /// Opens a stream on a stream service, with the items sent back in a single streamed
+    /// response.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the request could not be made or the server refused to open the stream.
+    /// Errors after this point are yielded by the stream, which ends after the first one. This
+    /// includes the response ending before the server closed the stream, which fails with
+    /// [`TransportError::UnexpectedEnd`].
+    ///
+    /// Only opening the stream is reported to the transport's metrics.
+    pub async fn stream<Req, Item>(
+        &self,
+        service: &'static str,
+        request: Req,
+        format: StreamFormat,
+    ) -> Result<
+        impl Stream<Item = Result<Item, TransportError>> + Unpin + use<Req, Item>,
+        TransportError,
+    >
+    where
+        Req: Serialize + ServiceRequest,
+        Item: DeserializeOwned,
+    {
+        let timer = self
+            .metrics
+            .as_ref()
+            .map(|metrics| metrics.start(Side::Client, service, request.fn_name()));
+
+        let mut sizes = PayloadSizes::default();
+        let result = self
+            .open_stream(service, &request, format, &mut sizes)
+            .await;
+        if let Some(timer) = timer {
+            let outcome = match &result {
+                Ok(_) => Outcome::Ok,
+                Err(TransportError::Handler(err)) => Outcome::Error { code: &err.code },
+                Err(_) => Outcome::Failed,
+            };
+            timer.finish(outcome, sizes);
+        }
+        let response = result?;
+
+        let state = FrameReader {
+            body: Box::pin(response.bytes_stream()),
+            decoder: FrameDecoder::new(format, self.max_response_size),
+            ended: false,
+            done: false,
+        };
+        Ok(Box::pin(stream::unfold(state, FrameReader::next)))
+    }
+
+    async fn open_stream<Req>(
+        &self,
+        service: &str,
+        request: &Req,
+        format: StreamFormat,
+        sizes: &mut PayloadSizes,
+    ) -> Result<reqwest::Response, TransportError>
+    where
+        Req: Serialize + ServiceRequest,
+    {
+        let (request, size) = self.post_call(service, request)?;
+        sizes.request = Some(size);
+
+        let request = request.header(ACCEPT, HeaderValue::from_static(format.content_type()));
+        self.execute(request).await
+    }
+}
+
+struct FrameReader<B> {
+    body: B,
+    decoder: FrameDecoder,
+    /// Whether the whole body has been read, although there may still be frames to decode.
+    ended: bool,
+    done: bool,
+}
+
+impl<B, C> FrameReader<B>
+where
+    B: Stream<Item = reqwest::Result<C>> + Unpin,
+    C: AsRef<[u8]>,
+{
+    async fn next<Item>(mut self) -> Option<(Result<Item, TransportError>, Self)>
+    where
+        Item: DeserializeOwned,
+    {
+        while !self.done {
+            if let Some(frame) = self.decoder.next_frame() {
+                let frame: StreamFrame<'static, Item> = match serde_json::from_slice(&frame) {
+                    Ok(frame) => frame,
+                    Err(err) => {
+                        self.done = true;
+                        return Some((Err(err.into()), self));
+                    }
+                };
+
+                return match frame {
+                    StreamFrame::StreamMessage { data } => Some((Ok(data), self)),
+                    StreamFrame::StreamClose => None,
+                    StreamFrame::StreamError { error } => {
+                        self.done = true;
+                        Some((Err(error.into()), self))
+                    }
+                };
+            }
+
+            // Streams always end with a frame, so a body without one was cut off
+            if self.ended {
+                self.done = true;
+                return Some((Err(TransportError::UnexpectedEnd), self));
+            }
+
+            match self.body.next().await {
+                Some(Ok(chunk)) => {
+                    if let Err(err) = self.decoder.push(chunk.as_ref()) {
+                        self.done = true;
+                        return Some((Err(err), self));
+                    }
+                }
+                Some(Err(err)) => {
+                    self.done = true;
+                    return Some((Err(err.into()), self));
+                }
+                None => {
+                    self.ended = true;
+                    if let Err(err) = self.decoder.finish() {
+                        self.done = true;
+                        return Some((Err(err), self));
+                    }
+                }
+            }
+        }
+
+        None
+    }
+}
+
+/// Splits a streamed response body into the JSON of each frame.
+///
+/// The size limit applies to each frame rather than the whole response, as streams may be
+/// open for any amount of time.
+struct FrameDecoder {
+    format: StreamFormat,
+    limit: Option<usize>,
+    buffer: Vec<u8>,
+    frames: VecDeque<Vec<u8>>,
+    event: Vec<u8>,
+}
+
+impl FrameDecoder {
+    fn new(format: StreamFormat, limit: Option<usize>) -> Self {
+        Self {
+            format,
+            limit,
+            buffer: Vec::new(),
+            frames: VecDeque::new(),
+            event: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, chunk: &[u8]) -> Result<(), TransportError> {
+        // The buffer never holds a whole line between chunks, so only the new chunk is scanned
+        let mut scan = self.buffer.len();
+        self.buffer.extend_from_slice(chunk);
+
+        let mut start = 0;
+        while let Some(offset) = self.buffer[scan..].iter().position(|b| *b == b'\n') {
+            let end = scan + offset;
+            let line = self.buffer[start..end].to_vec();
+            self.push_line(line)?;
+            start = end + 1;
+            scan = start;
+        }
+        self.buffer.drain(..start);
+
+        // The rest of the buffer is the start of a frame that hasn't finished yet
+        self.check_limit(self.buffer.len() + self.event.len())
+    }
+
+    /// Ends the body, treating a last line or event that wasn't ended as finished.
+    fn finish(&mut self) -> Result<(), TransportError> {
+        if !self.buffer.is_empty() {
+            let line = std::mem::take(&mut self.buffer);
+            self.push_line(line)?;
+        }
+        if !self.event.is_empty() {
+            self.frames.push_back(std::mem::take(&mut self.event));
+        }
+        Ok(())
+    }
+
+    fn push_line(&mut self, mut line: Vec<u8>) -> Result<(), TransportError> {
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+
+        match self.format {
+            StreamFormat::NdJson => {
+                if !line.is_empty() {
+                    self.check_limit(line.len())?;
+                    self.frames.push_back(line);
+                }
+            }
+            // Only the data field is used, so event names, ids and comments are all skipped.
+            // Multiple data lines in one event are joined with newlines, as per the spec.
+            StreamFormat::EventStream => {
+                if line.is_empty() {
+                    if !self.event.is_empty() {
+                        self.frames.push_back(std::mem::take(&mut self.event));
+                    }
+                } else if let Some(data) = line.strip_prefix(b"data:") {
+                    if !self.event.is_empty() {
+                        self.event.push(b'\n');
+                    }
+                    self.event
+                        .extend_from_slice(data.strip_prefix(b" ").unwrap_or(data));
+                    self.check_limit(self.event.len())?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_limit(&self, size: usize) -> Result<(), TransportError> {
+        match self.limit {
+            Some(limit) if size > limit => Err(TransportError::TooLarge { limit }),
+            _ => Ok(()),
+        }
+    }
+
+    fn next_frame(&mut self) -> Option<Vec<u8>> {
+        self.frames.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use super::*;
+
+    /// Reads every item from a body sent in the given chunks.
+    fn read(format: StreamFormat, chunks: &[&str]) -> Vec<Result<u32, TransportError>> {
+        let body = stream::iter(
+            chunks
+                .iter()
+                .map(|chunk| Ok::<_, reqwest::Error>(chunk.as_bytes())),
+        );
+        let state = FrameReader {
+            body,
+            decoder: FrameDecoder::new(format, Some(64)),
+            ended: false,
+            done: false,
+        };
+        block_on(stream::unfold(state, FrameReader::next).collect())
+    }
+
+    fn items(results: &[Result<u32, TransportError>]) -> Vec<u32> {
+        results
+            .iter()
+            .filter_map(|result| result.as_ref().ok().copied())
+            .collect()
+    }
+
+    #[test]
+    fn ndjson_frames_split_across_chunks() {
+        let results = read(
+            StreamFormat::NdJson,
+            &[
+                "{\"type\":\"stream_message\",\"data\":1}\n{\"type\":\"stream_",
+                "message\",\"data\":2}\r\n",
+                "{\"type\":\"stream_close\"}\n",
+            ],
+        );
+        assert_eq!(items(&results), [1, 2]);
+        assert_eq!(results.len(), 2);
+    }
+
+    #[test]
+    fn event_stream_frames() {
+        let results = read(
+            StreamFormat::EventStream,
+            &[
+                ": comment\nevent: item\ndata: {\"type\":\"stream_message\",\n",
+                "data: \"data\":1}\n\n",
+                "data: {\"type\":\"stream_close\"}\n\n",
+            ],
+        );
+        assert_eq!(items(&results), [1]);
+        assert_eq!(results.len(), 1);
+    }
+
+    #[test]
+    fn long_lines_are_read_a_byte_at_a_time() {
+        let frame = "{\"type\":\"stream_message\",\"data\":7}\n{\"type\":\"stream_close\"}\n";
+        let chunks: Vec<&str> = (0..frame.len()).map(|i| &frame[i..=i]).collect();
+        let results = read(StreamFormat::NdJson, &chunks);
+        assert_eq!(items(&results), [7]);
+        assert_eq!(results.len(), 1);
+    }
+
+    #[test]
+    fn last_frame_without_a_newline_is_read() {
+        let results = read(
+            StreamFormat::NdJson,
+            &["{\"type\":\"stream_message\",\"data\":1}\n{\"type\":\"stream_close\"}"],
+        );
+        assert_eq!(items(&results), [1]);
+        assert_eq!(results.len(), 1);
+
+        let results = read(
+            StreamFormat::EventStream,
+            &["data: {\"type\":\"stream_close\"}"],
+        );
+        assert!(results.is_empty());
+    }
+
+    #[test]
+    fn body_ending_before_the_close_fails() {
+        let results = read(
+            StreamFormat::NdJson,
+            &["{\"type\":\"stream_message\",\"data\":1}\n"],
+        );
+        assert_eq!(items(&results), [1]);
+        assert!(matches!(
+            results.last(),
+            Some(Err(TransportError::UnexpectedEnd))
+        ));
+
+        let results = read(StreamFormat::EventStream, &[]);
+        assert!(matches!(
+            results.as_slice(),
+            [Err(TransportError::UnexpectedEnd)]
+        ));
+    }
+
+    #[test]
+    fn partial_last_frame_fails() {
+        let results = read(
+            StreamFormat::NdJson,
+            &["{\"type\":\"stream_message\",\"data\":1}\n{\"type\":\"stream_mes"],
+        );
+        assert_eq!(items(&results), [1]);
+        assert!(matches!(results.last(), Some(Err(TransportError::Json(_)))));
+        assert_eq!(results.len(), 2);
+    }
+
+    #[test]
+    fn frames_over_the_limit_fail() {
+        let data = "x".repeat(64);
+        let frame = format!("{{\"type\":\"stream_message\",\"data\":\"{data}\"}}\n");
+        let results = read(StreamFormat::NdJson, &[&frame]);
+        assert!(matches!(
+            results.as_slice(),
+            [Err(TransportError::TooLarge { limit: 64 })]
+        ));
+
+        // Frames are checked before they end, so a large frame isn't buffered in full
+        let results = read(StreamFormat::EventStream, &["data: ", &frame]);
+        assert!(matches!(
+            results.as_slice(),
+            [Err(TransportError::TooLarge { limit: 64 })]
+        ));
+    }
+}
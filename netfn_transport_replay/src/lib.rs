@@ -0,0 +1,411 @@
+#![warn(clippy::pedantic)]
+
+//! Transports that record calls to a file and replay them later without a server.
+//!
+//! Recordings are JSON lines, with one [`Exchange`] per line, so they can be read and edited by
+//! hand. As responses are recorded as JSON, the inner transport must use a self-describing
+//! encoding.
+
+use std::{
+    fmt::Display,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::{Mutex, PoisonError},
+};
+
+use netfn_core::{GenericError, HandlerError, ServiceRequest, Transport, compat};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::Value;
+use thiserror::Error;
+
+/// A single call and its result.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Exchange {
+    pub service: String,
+    pub request: Value,
+    #[serde(flatten)]
+    pub result: ExchangeResult,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExchangeResult {
+    Response(Value),
+    /// The handler returned an error, which is replayed as it was sent.
+    HandlerError(GenericError<'static>),
+    /// The transport failed, which is recorded as the error message.
+    Error(String),
+}
+
+/// Records every call made through the inner transport.
+///
+/// Each exchange is written as soon as the call finishes, so a recording is kept even if the
+/// process doesn't exit cleanly.
+///
+/// The writes block, and are made from within `call` while holding a lock, so the writer should
+/// be quick, such as a buffered file. Slower writers, such as ones over a network, should send
+/// the lines to another thread to be written instead.
+pub struct RecordTransport<T> {
+    inner: T,
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+impl<T> RecordTransport<T> {
+    pub fn new(inner: T, writer: impl Write + Send + 'static) -> Self {
+        Self {
+            inner,
+            writer: Mutex::new(Box::new(writer)),
+        }
+    }
+
+    /// Records to a file, replacing it if it exists.
+    #[allow(clippy::missing_errors_doc)]
+    pub fn create(inner: T, path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::new(inner, BufWriter::new(File::create(path)?)))
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    fn write(&self, exchange: &Exchange) -> Result<(), RecordError<T::Error>>
+    where
+        T: Transport,
+    {
+        let mut line = serde_json::to_vec(exchange)?;
+        line.push(b'\n');
+
+        let mut writer = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+        writer.write_all(&line)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<T> Transport for RecordTransport<T>
+where
+    T: Transport + compat::NetfnSync,
+    T::Error: Display + HandlerError + compat::NetfnSend,
+{
+    type Error = RecordError<T::Error>;
+
+    async fn call<Req, Res>(&self, service: &'static str, request: Req) -> Result<Res, Self::Error>
+    where
+        Req: compat::NetfnSend + Serialize + ServiceRequest,
+        Res: compat::NetfnSend + DeserializeOwned,
+    {
+        let recorded = serde_json::to_value(&request)?;
+        let result = self.inner.call::<_, Value>(service, request).await;
+
+        let exchange = Exchange {
+            service: service.to_owned(),
+            request: recorded,
+            result: match &result {
+                Ok(response) => ExchangeResult::Response(response.clone()),
+                Err(err) => match err.handler_error() {
+                    Some(err) => ExchangeResult::HandlerError(err.clone()),
+                    None => ExchangeResult::Error(err.to_string()),
+                },
+            },
+        };
+        self.write(&exchange)?;
+
+        let response = result.map_err(RecordError::Transport)?;
+        Ok(serde_json::from_value(response)?)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum RecordError<E> {
+    #[error(transparent)]
+    Transport(E),
+    #[error("failed to encode or decode the call: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("failed to write the recording: {0}")]
+    Io(#[from] io::Error),
+}
+
+impl<E> HandlerError for RecordError<E>
+where
+    E: HandlerError,
+{
+    fn handler_error(&self) -> Option<&GenericError<'static>> {
+        match self {
+            Self::Transport(err) => err.handler_error(),
+            _ => None,
+        }
+    }
+}
+
+/// Responds to calls using a recording, without making any requests.
+///
+/// Calls are matched on the service and the encoded request. If the same call was recorded
+/// several times, the responses are replayed in the order they were recorded, with the last one
+/// being repeated once the others have been used.
+#[derive(Debug)]
+pub struct ReplayTransport {
+    exchanges: Mutex<Vec<(Exchange, bool)>>,
+}
+
+impl ReplayTransport {
+    #[must_use]
+    pub fn new(exchanges: impl IntoIterator<Item = Exchange>) -> Self {
+        Self {
+            exchanges: Mutex::new(
+                exchanges
+                    .into_iter()
+                    .map(|exchange| (exchange, false))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Reads a recording made by [`RecordTransport`].
+    #[allow(clippy::missing_errors_doc)]
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, ReplayError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    #[allow(clippy::missing_errors_doc)]
+    pub fn parse(recording: &str) -> Result<Self, ReplayError> {
+        let exchanges = recording
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self::new(exchanges))
+    }
+
+    fn take(&self, service: &str, request: &Value) -> Option<ExchangeResult> {
+        let mut exchanges = self
+            .exchanges
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let mut last = None;
+        for (exchange, used) in exchanges.iter_mut() {
+            if exchange.service != service || exchange.request != *request {
+                continue;
+            }
+            if !*used {
+                *used = true;
+                return Some(exchange.result.clone());
+            }
+            last = Some(&exchange.result);
+        }
+        last.cloned()
+    }
+}
+
+impl Transport for ReplayTransport {
+    type Error = ReplayError;
+
+    async fn call<Req, Res>(&self, service: &'static str, request: Req) -> Result<Res, Self::Error>
+    where
+        Req: compat::NetfnSend + Serialize + ServiceRequest,
+        Res: compat::NetfnSend + DeserializeOwned,
+    {
+        let request = serde_json::to_value(request)?;
+        match self.take(service, &request) {
+            Some(ExchangeResult::Response(response)) => Ok(serde_json::from_value(response)?),
+            Some(ExchangeResult::HandlerError(err)) => Err(ReplayError::Handler(err)),
+            Some(ExchangeResult::Error(message)) => Err(ReplayError::Failed(message)),
+            None => Err(ReplayError::NotRecorded {
+                service: service.to_owned(),
+                request: request.to_string(),
+            }),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ReplayError {
+    #[error("no call to {service} with {request} was recorded")]
+    NotRecorded { service: String, request: String },
+    /// The handler returned an error when the call was recorded.
+    #[error("{0}")]
+    Handler(GenericError<'static>),
+    /// The transport failed when the call was recorded.
+    #[error("{0}")]
+    Failed(String),
+    #[error("failed to encode or decode the call: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("failed to read the recording: {0}")]
+    Io(#[from] io::Error),
+}
+
+impl HandlerError for ReplayError {
+    fn handler_error(&self) -> Option<&GenericError<'static>> {
+        match self {
+            Self::Handler(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        future,
+        sync::{
+            Arc,
+            atomic::{AtomicU32, Ordering},
+        },
+    };
+
+    use futures::executor::block_on;
+    use netfn_core::{Service, error_codes, server::Dispatcher};
+
+    use super::*;
+
+    /// Counts up from 1, so each call has a different response.
+    #[derive(Default)]
+    struct Counter(AtomicU32);
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(tag = "fn", content = "args")]
+    enum CounterRequest {
+        Next {},
+    }
+
+    impl ServiceRequest for CounterRequest {
+        fn fn_name(&self) -> &'static str {
+            "Next"
+        }
+
+        fn read_only(&self) -> bool {
+            false
+        }
+    }
+
+    impl Service for Counter {
+        const NAME: &'static str = "Counter";
+        type Request = CounterRequest;
+        type Response = u32;
+
+        fn call(&self, _: Self::Request) -> impl Future<Output = Self::Response> + Send {
+            future::ready(self.0.fetch_add(1, Ordering::Relaxed) + 1)
+        }
+    }
+
+    /// A writer whose contents can be read while it is owned by the transport.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl SharedBuffer {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn record(calls: impl FnOnce(&RecordTransport<Dispatcher>)) -> String {
+        let buffer = SharedBuffer::default();
+        let transport = RecordTransport::new(
+            Dispatcher::new().with_service(Counter::default()),
+            buffer.clone(),
+        );
+        calls(&transport);
+        buffer.contents()
+    }
+
+    fn next<T>(transport: &T) -> Result<u32, T::Error>
+    where
+        T: Transport,
+    {
+        block_on(transport.call("Counter", CounterRequest::Next {}))
+    }
+
+    fn missing<T>(transport: &T) -> Result<u32, T::Error>
+    where
+        T: Transport,
+    {
+        block_on(transport.call("Missing", CounterRequest::Next {}))
+    }
+
+    #[test]
+    fn records_each_call_as_a_line() {
+        let recording = record(|transport| {
+            assert_eq!(next(transport).unwrap(), 1);
+            assert!(missing(transport).is_err());
+        });
+
+        let exchanges: Vec<Exchange> = recording
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(exchanges.len(), 2);
+        assert_eq!(exchanges[0].service, "Counter");
+        assert_eq!(
+            exchanges[0].request,
+            serde_json::json!({ "fn": "Next", "args": {} })
+        );
+        assert_eq!(
+            exchanges[0].result,
+            ExchangeResult::Response(Value::from(1))
+        );
+        assert!(matches!(
+            &exchanges[1].result,
+            ExchangeResult::HandlerError(err) if err.code == error_codes::NOT_FOUND
+        ));
+    }
+
+    #[test]
+    fn replays_in_order_then_repeats_the_last() {
+        let recording = record(|transport| {
+            for _ in 0..3 {
+                next(transport).unwrap();
+            }
+        });
+
+        let replay = ReplayTransport::parse(&recording).unwrap();
+        let responses: Vec<u32> = (0..5).map(|_| next(&replay).unwrap()).collect();
+        assert_eq!(responses, [1, 2, 3, 3, 3]);
+    }
+
+    #[test]
+    fn replays_handler_errors_as_they_were_sent() {
+        let mut recorded = None;
+        let recording = record(|transport| {
+            recorded = missing(transport).unwrap_err().handler_error().cloned();
+        });
+
+        let replay = ReplayTransport::parse(&recording).unwrap();
+        let err = missing(&replay).unwrap_err();
+        assert!(matches!(err, ReplayError::Handler(_)));
+        assert_eq!(err.handler_error(), recorded.as_ref());
+    }
+
+    #[test]
+    fn replays_transport_errors_as_failures() {
+        let recording = concat!(
+            r#"{"service":"Counter","request":{"fn":"Next","args":{}},"error":"connection refused"}"#,
+            "\n",
+        );
+
+        let replay = ReplayTransport::parse(recording).unwrap();
+        let err = next(&replay).unwrap_err();
+        assert!(matches!(&err, ReplayError::Failed(message) if message == "connection refused"));
+        assert_eq!(err.handler_error(), None);
+    }
+
+    #[test]
+    fn unrecorded_calls_fail() {
+        let replay = ReplayTransport::parse("").unwrap();
+        assert!(matches!(
+            next(&replay),
+            Err(ReplayError::NotRecorded { service, .. }) if service == "Counter"
+        ));
+    }
+}
@@ -1,11 +1,19 @@
-use std::{collections::HashMap, marker::PhantomData};
+#[cfg(feature = "server")]
+pub mod server;
+
+use std::{collections::HashMap, fmt, marker::PhantomData, pin::Pin};
 
 use futures::{
-    Sink, SinkExt as _, Stream, StreamExt as _,
+    FutureExt as _, Sink, SinkExt as _, Stream, StreamExt as _,
     channel::{mpsc, oneshot},
+    future::{self, Fuse},
     select,
 };
-use netfn_core::{CallResponseRequest, Transport, TunnelRequest};
+use netfn_core::{
+    CallResponseRequest, GenericError, HandlerError, ServiceRequest, Transport, TunnelCallError,
+    TunnelMessage, TunnelRequest, TunnelResponse,
+    metrics::{Metrics, Outcome, PayloadSizes, Side},
+};
 use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use thiserror::Error;
 
@@ -14,6 +22,22 @@ pub enum WebSocketMessage {
     MessagePack(Vec<u8>),
 }
 
+impl WebSocketMessage {
+    /// The size of the message payload, in bytes.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Json(json) => json.len(),
+            Self::MessagePack(bytes) => bytes.len(),
+        }
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
 pub trait WebSocketCodec:
     Clone + netfn_core::compat::NetfnSend + netfn_core::compat::NetfnSync
 {
@@ -39,6 +63,7 @@ pub struct WebSocketTransport<Codec, SinkError> {
     codec: Codec,
     ref_sx: mpsc::Sender<oneshot::Sender<u64>>,
     msg_sx: mpsc::Sender<BusMsg<SinkError>>,
+    metrics: Option<Metrics>,
     _sink_err: PhantomData<SinkError>,
 }
 
@@ -55,7 +80,8 @@ impl<Codec, SinkError> WebSocketTransport<Codec, SinkError> {
                 codec: codec.clone(),
                 ref_sx,
                 msg_sx,
-                _sink_err: PhantomData::default(),
+                metrics: None,
+                _sink_err: PhantomData,
             },
             WebSocketListener {
                 codec,
@@ -63,9 +89,20 @@ impl<Codec, SinkError> WebSocketTransport<Codec, SinkError> {
                 msg_rx,
                 close_sx,
                 close_rx,
+                max_message_size: None,
+                on_event: None,
+                metrics: None,
+                listened: false,
             },
         )
     }
+
+    /// Reports every call made through the transport.
+    #[must_use]
+    pub fn metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
 }
 
 #[derive(Debug)]
@@ -73,110 +110,326 @@ pub struct WebSocketListener<Codec, SinkError> {
     codec: Codec,
     ref_rx: mpsc::Receiver<oneshot::Sender<u64>>,
     msg_rx: mpsc::Receiver<BusMsg<SinkError>>,
-    close_sx: mpsc::Sender<()>,
-    close_rx: mpsc::Receiver<()>,
+    close_sx: mpsc::Sender<Close>,
+    close_rx: mpsc::Receiver<Close>,
+    max_message_size: Option<usize>,
+    on_event: Option<EventHandler>,
+    metrics: Option<Metrics>,
+    listened: bool,
+}
+
+/// Something that happened in a [`WebSocketListener`] that callers aren't told about directly.
+///
+/// These are passed to the handler set with [`WebSocketListener::on_event`], and are logged if the
+/// `tracing` feature is enabled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ListenerEvent {
+    /// A call couldn't be sent through the sink, and failed with [`TransportError::SendSink`].
+    SendFailed { msg_ref: u64 },
+    /// A message couldn't be decoded, so it was dropped.
+    DecodeFailed { size: usize },
+    /// A message without a ref was received, such as a stream message, so it was dropped.
+    MissingRef,
+    /// A message was received for a ref that isn't waiting for a response, so it was dropped.
+    UnknownRef { msg_ref: u64 },
+    /// A response was received for a call whose caller stopped waiting for it.
+    Abandoned { msg_ref: u64 },
+    /// A message was over the size limit, which closes the connection.
+    TooLarge { size: usize, limit: usize },
+    /// The listener started draining, with this many calls waiting for a response.
+    Draining { in_flight: usize },
+    /// The listener stopped, failing the calls that were still waiting for a response.
+    Stopped { disconnected: usize },
+}
+
+impl fmt::Display for ListenerEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SendFailed { msg_ref } => write!(f, "failed to send call {msg_ref}"),
+            Self::DecodeFailed { size } => {
+                write!(
+                    f,
+                    "dropped a message of {size} bytes that couldn't be decoded"
+                )
+            }
+            Self::MissingRef => write!(f, "dropped a message without a ref"),
+            Self::UnknownRef { msg_ref } => {
+                write!(
+                    f,
+                    "dropped a message for ref {msg_ref}, which isn't in flight"
+                )
+            }
+            Self::Abandoned { msg_ref } => {
+                write!(
+                    f,
+                    "dropped the response to call {msg_ref}, as its caller is gone"
+                )
+            }
+            Self::TooLarge { size, limit } => write!(
+                f,
+                "closing after a message of {size} bytes, over the limit of {limit} bytes"
+            ),
+            Self::Draining { in_flight } => {
+                write!(f, "draining with {in_flight} calls in flight")
+            }
+            Self::Stopped { disconnected } => {
+                write!(f, "stopped, disconnecting {disconnected} calls")
+            }
+        }
+    }
+}
+
+trait EventFn:
+    Fn(ListenerEvent) + netfn_core::compat::NetfnSend + netfn_core::compat::NetfnSync
+{
+}
+impl<F> EventFn for F where
+    F: Fn(ListenerEvent) + netfn_core::compat::NetfnSend + netfn_core::compat::NetfnSync
+{
+}
+
+struct EventHandler(Box<dyn EventFn>);
+
+impl fmt::Debug for EventHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EventHandler")
+    }
+}
+
+trait DrainDeadline: Future<Output = ()> + netfn_core::compat::NetfnSend {}
+impl<F> DrainDeadline for F where F: Future<Output = ()> + netfn_core::compat::NetfnSend {}
+
+enum Close {
+    Now,
+    Drain(Pin<Box<dyn DrainDeadline>>),
 }
 
 impl<Codec, SinkError> WebSocketListener<Codec, SinkError>
 where
     Codec: WebSocketCodec,
 {
+    /// Sets the maximum size of a received message, in bytes.
+    ///
+    /// Messages are checked before being decoded, and a message over the limit closes the
+    /// connection, failing every in-flight call with [`TransportError::TooLarge`]. Messages are
+    /// buffered by the socket before they get here, so its own limit (such as tungstenite's
+    /// `max_message_size`) should be set as well.
+    #[must_use]
+    pub fn max_message_size(mut self, limit: usize) -> Self {
+        self.max_message_size = Some(limit);
+        self
+    }
+
+    /// Sets a handler for [`ListenerEvent`]s, which is called from the listen loop, so it should
+    /// return quickly.
+    #[must_use]
+    pub fn on_event(
+        mut self,
+        handler: impl Fn(ListenerEvent)
+        + netfn_core::compat::NetfnSend
+        + netfn_core::compat::NetfnSync
+        + 'static,
+    ) -> Self {
+        self.on_event = Some(EventHandler(Box::new(handler)));
+        self
+    }
+
+    /// Reports each time the listener is run again after stopping as a reconnect.
+    #[must_use]
+    pub fn metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    fn emit(&self, event: ListenerEvent) {
+        #[cfg(feature = "tracing")]
+        match event {
+            ListenerEvent::Abandoned { .. }
+            | ListenerEvent::Draining { .. }
+            | ListenerEvent::Stopped { disconnected: 0 } => tracing::debug!(?event, "{event}"),
+            _ => tracing::warn!(?event, "{event}"),
+        }
+
+        if let Some(EventHandler(handler)) = &self.on_event {
+            handler(event);
+        }
+    }
+
     pub fn closer(&self) -> WebSocketListenerCloser {
         WebSocketListenerCloser {
             close_sx: self.close_sx.clone(),
         }
     }
 
-    pub async fn listen<Sx, Rx>(&mut self, sink: &mut Sx, stream: &mut Rx)
+    /// Runs the tunnel until the stream ends or the listener is closed, then closes the sink and
+    /// returns the result of closing it.
+    ///
+    /// Calls that are still waiting for a response when the listener stops fail with
+    /// [`TransportError::Disconnected`], while calls that haven't been sent yet wait for the
+    /// listener to be run again.
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn listen<Sx, Rx>(&mut self, sink: &mut Sx, stream: &mut Rx) -> Result<(), SinkError>
     where
         Sx: Sink<WebSocketMessage, Error = SinkError> + Unpin,
         Rx: Stream<Item = WebSocketMessage> + Unpin,
     {
         let mut stream = stream.fuse();
 
+        if let Some(metrics) = &self.metrics
+            && self.listened
+        {
+            metrics.reconnected("websocket");
+        }
+        self.listened = true;
+
         let mut reqs = HashMap::new();
         let mut cref = 0;
+        let mut draining = false;
+        let mut deadline: Fuse<Pin<Box<dyn DrainDeadline>>> = Fuse::terminated();
 
         enum Bus<SinkErr> {
             RefRequest(oneshot::Sender<u64>),
             Message(BusMsg<SinkErr>),
             Stream(WebSocketMessage),
+            Drain(Pin<Box<dyn DrainDeadline>>),
         }
 
         // We loop against all these together to maintain some form of sanity.
         // While this _could_ bottleneck requests, it means that clients cannot get out
         // of sync with each other, and by forcing clients to ask for reference IDs from
         // here it ensures that the counter is always unique for the current tunnel.
-        // If the tunnel closes and reopens, in-flight requests get retried and the
-        // IDs they need will only be given once the counter has been reset.
+        // If the tunnel closes and reopens, calls that were sent fail with `Disconnected`, while
+        // calls that weren't sent are retried with IDs that are only given once the counter has
+        // been reset.
         while let Some(s) = select! {
-            ref_req = self.ref_rx.next() => ref_req.map(Bus::RefRequest),
+            // No new IDs are given out while draining, so new calls wait for the next run
+            ref_req = async {
+                if draining {
+                    future::pending().await
+                } else {
+                    self.ref_rx.next().await
+                }
+            }.fuse() => ref_req.map(Bus::RefRequest),
             bus = self.msg_rx.next() => bus.map(Bus::Message),
             stream = stream.next() => stream.map(Bus::Stream),
-            _ = self.close_rx.next() => None,
+            close = self.close_rx.next() => match close {
+                Some(Close::Drain(deadline)) => Some(Bus::Drain(deadline)),
+                Some(Close::Now) | None => None,
+            },
+            () = deadline => None,
         } {
             match s {
                 Bus::RefRequest(ref_req_sx) => {
                     let _ = ref_req_sx.send(cref);
                     cref += 1;
                 }
-                Bus::Message((msg_ref, req, response_sx)) => {
-                    match sink.send(req).await {
-                        Ok(()) => {
-                            reqs.insert(msg_ref, response_sx);
-                        }
-                        Err(err) => {
-                            // If we cant respond, there's nothing we can do but sulk
-                            // TODO: add some warn log here
-                            let _ = response_sx.send(Err(BusError::Sink(SinkError(err))));
-                        }
+                Bus::Drain(drain_deadline) => {
+                    self.emit(ListenerEvent::Draining {
+                        in_flight: reqs.len(),
+                    });
+                    if reqs.is_empty() {
+                        break;
                     }
+                    draining = true;
+                    deadline = drain_deadline.fuse();
                 }
+                Bus::Message((_, _, response_sx)) if draining => {
+                    // Calls that got an ID before the drain started are retried on the next run.
+                    let _ = response_sx.send(Err(BusError::Closed));
+                }
+                Bus::Message((msg_ref, req, response_sx)) => match sink.send(req).await {
+                    Ok(()) => {
+                        reqs.insert(msg_ref, response_sx);
+                    }
+                    Err(err) => {
+                        self.emit(ListenerEvent::SendFailed { msg_ref });
+                        let _ = response_sx.send(Err(BusError::Sink(SinkError(err))));
+                    }
+                },
                 Bus::Stream(res) => {
+                    if let Some(limit) = self.max_message_size.filter(|limit| res.len() > *limit) {
+                        self.emit(ListenerEvent::TooLarge {
+                            size: res.len(),
+                            limit,
+                        });
+                        // We can't tell who the message was for without decoding it, so the
+                        // connection is dropped and every caller told why.
+                        for (_, response_sx) in reqs.drain() {
+                            let _ = response_sx.send(Err(BusError::TooLarge { limit }));
+                        }
+                        break;
+                    }
+
                     let Ok(PartialRefs { msg_ref, .. }) = self.codec.decode(&res) else {
-                        // TODO: log err somewhere
+                        self.emit(ListenerEvent::DecodeFailed { size: res.len() });
                         continue;
                     };
 
                     let Some(id) = msg_ref else {
                         // TODO: check handle
+                        self.emit(ListenerEvent::MissingRef);
                         continue;
                     };
 
                     let Some(response_sx) = reqs.remove(&id) else {
                         // If the other end has send us something we have no idea about then we cant
                         // do anything
-                        // TODO: add some warn log here
+                        self.emit(ListenerEvent::UnknownRef { msg_ref: id });
                         continue;
                     };
-                    // If we cant respond, there's nothing we can do but sulk
-                    // TODO: add some warn log here
-                    let _ = response_sx.send(Ok(res));
+                    if response_sx.send(Ok(res)).is_err() {
+                        self.emit(ListenerEvent::Abandoned { msg_ref: id });
+                    }
+
+                    if draining && reqs.is_empty() {
+                        break;
+                    }
                 }
             }
         }
 
-        // We respond to any in-flight requests that the bus has closed to make them
-        // try again.
+        // Calls that were sent can't be retried, as they may have already run, so they are told
+        // that the connection was lost.
+        self.emit(ListenerEvent::Stopped {
+            disconnected: reqs.len(),
+        });
+        for (_, response_sx) in reqs.drain() {
+            let _ = response_sx.send(Err(BusError::Disconnected));
+        }
+
+        // We respond to any calls that are queued but weren't sent that the bus has closed to
+        // make them try again.
         // Once they do, the first thing they will ask for is new IDs, which will only
         // be given once the bus reopens.
         while let Ok(Some((_, _, response_sx))) = self.msg_rx.try_next() {
             let _ = response_sx.send(Err(BusError::Closed));
         }
 
-        // TODO: we should bubble this back up
-        let _ = sink.close().await;
+        sink.close().await
     }
 }
 
 #[derive(Clone, Debug)]
 pub struct WebSocketListenerCloser {
-    close_sx: mpsc::Sender<()>,
+    close_sx: mpsc::Sender<Close>,
 }
 
 impl WebSocketListenerCloser {
+    /// Stops the listener straight away, failing any calls that are waiting for a response.
     pub fn close(&mut self) {
-        let _ = self.close_sx.try_send(());
+        let _ = self.close_sx.try_send(Close::Now);
+    }
+
+    /// Stops the listener once every call that has been sent has a response, or once `deadline`
+    /// finishes, whichever is first.
+    ///
+    /// New calls aren't sent while the listener drains, and wait for it to be run again instead.
+    pub fn drain(
+        &mut self,
+        deadline: impl Future<Output = ()> + netfn_core::compat::NetfnSend + 'static,
+    ) {
+        let _ = self.close_sx.try_send(Close::Drain(Box::pin(deadline)));
     }
 }
 
@@ -188,6 +441,52 @@ where
     type Error = TransportError<Codec::EncodeError, Codec::DecodeError, SinkError>;
 
     async fn call<Req, Res>(&self, service: &'static str, request: Req) -> Result<Res, Self::Error>
+    where
+        Req: Serialize + ServiceRequest,
+        Res: DeserializeOwned,
+    {
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!("netfn.call", service, fn_name = request.fn_name());
+
+        let timer = self
+            .metrics
+            .as_ref()
+            .map(|metrics| metrics.start(Side::Client, service, request.fn_name()));
+
+        let call = async {
+            let mut sizes = PayloadSizes::default();
+            let result = self.call_inner(service, request, &mut sizes).await;
+            if let Some(timer) = timer {
+                let outcome = match &result {
+                    Ok(_) => Outcome::Ok,
+                    Err(TransportError::Handler(err)) => Outcome::Error { code: &err.code },
+                    Err(_) => Outcome::Failed,
+                };
+                timer.finish(outcome, sizes);
+            }
+            #[cfg(feature = "tracing")]
+            if let Err(err) = &result {
+                tracing::debug!(error = %err, "call failed");
+            }
+            result
+        };
+
+        #[cfg(feature = "tracing")]
+        let call = tracing::Instrument::instrument(call, span);
+        call.await
+    }
+}
+
+impl<Codec, SinkError> WebSocketTransport<Codec, SinkError>
+where
+    Codec: WebSocketCodec,
+{
+    async fn call_inner<Req, Res>(
+        &self,
+        service: &str,
+        request: Req,
+        sizes: &mut PayloadSizes,
+    ) -> Result<Res, TransportError<Codec::EncodeError, Codec::DecodeError, SinkError>>
     where
         Req: Serialize,
         Res: DeserializeOwned,
@@ -205,27 +504,36 @@ where
             // Construct the request and send it
             let (response_sx, response_rx) = oneshot::channel();
             let request = codec
-                .encode(&TunnelRequest {
+                .encode(&TunnelMessage::Request(TunnelRequest {
                     msg_ref,
                     payload: CallResponseRequest {
                         service: service.into(),
                         call: &request,
                     },
-                })
+                }))
                 .map_err(|e| TransportError::EncodeError(e))?;
+            sizes.request = Some(request.len());
             msg_sx.send((msg_ref, request, response_sx)).await?;
 
             // Wait on the response, looping back if the bus closed mid-request.
             let result = match response_rx.await? {
                 Ok(result) => result,
                 Err(BusError::Sink(err)) => return Err(err.into()),
+                Err(BusError::TooLarge { limit }) => {
+                    return Err(TransportError::TooLarge { limit });
+                }
+                Err(BusError::Disconnected) => return Err(TransportError::Disconnected),
                 Err(BusError::Closed) => continue,
             };
 
+            sizes.response = Some(result.len());
             let result = codec
                 .decode(&result)
                 .map_err(|e| TransportError::DecodeError(e))?;
-            break Ok(result);
+            break match result {
+                TunnelReply::Response(response) => Ok(response.data),
+                TunnelReply::Error(err) => Err(TransportError::Handler(err.error)),
+            };
         }
     }
 }
@@ -242,6 +550,32 @@ pub enum TransportError<EncodeError, DecodeError, SinkError> {
     EncodeError(#[source] EncodeError),
     #[error("failed to decode message")]
     DecodeError(#[source] DecodeError),
+    #[error("message is larger than the limit of {limit} bytes")]
+    TooLarge { limit: usize },
+    /// The connection closed after the call was sent, so it may or may not have run.
+    #[error("the connection closed before a response was received")]
+    Disconnected,
+    #[error("{0}")]
+    Handler(GenericError<'static>),
+}
+
+impl<EncodeError, DecodeError, SinkError> HandlerError
+    for TransportError<EncodeError, DecodeError, SinkError>
+{
+    fn handler_error(&self) -> Option<&GenericError<'static>> {
+        match self {
+            Self::Handler(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// The messages that can be sent in reply to a call.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum TunnelReply<T> {
+    Response(TunnelResponse<T>),
+    Error(TunnelCallError<'static>),
 }
 
 struct SinkError<E>(E);
@@ -256,7 +590,9 @@ impl<EncodeError, DecodeError, E> From<SinkError<E>>
 
 enum BusError<E> {
     Sink(SinkError<E>),
+    TooLarge { limit: usize },
     Closed,
+    Disconnected,
 }
 
 #[derive(Deserialize)]
@@ -0,0 +1,222 @@
+//! Serving the other end of a tunnel with a [`Dispatcher`].
+
+use std::collections::HashMap;
+
+use futures::{
+    FutureExt as _, Sink, SinkExt as _, Stream, StreamExt as _,
+    future::{self, BoxFuture},
+    select,
+    stream::{self, AbortHandle, BoxStream, FuturesUnordered, SelectAll},
+};
+use netfn_core::{
+    GenericError, TunnelCallError, TunnelMessage, TunnelResponse, TunnelStreamClose,
+    TunnelStreamError, TunnelStreamMessage, TunnelStreamOpenError, TunnelStreamReady, error_codes,
+    server::{DispatchResult, Dispatcher, Extensions},
+};
+use serde_json::Value;
+
+use crate::{PartialRefs, TransportError, WebSocketCodec, WebSocketMessage};
+
+type Reply = TunnelMessage<'static, Value>;
+
+/// Configuration for serving tunnels, which can serve any number of them at once.
+#[derive(Debug)]
+pub struct TunnelServer<Codec> {
+    dispatcher: Dispatcher,
+    codec: Codec,
+    max_message_size: Option<usize>,
+}
+
+impl<Codec> TunnelServer<Codec>
+where
+    Codec: WebSocketCodec,
+{
+    #[must_use]
+    pub fn new(dispatcher: Dispatcher, codec: Codec) -> Self {
+        Self {
+            dispatcher,
+            codec,
+            max_message_size: None,
+        }
+    }
+
+    /// Sets the maximum size of a received message, in bytes.
+    ///
+    /// Messages are checked before being decoded, so a message over the limit can't be answered,
+    /// and closes the tunnel with [`TransportError::TooLarge`] instead. As with the listener, the
+    /// socket's own limit should be set as well.
+    #[must_use]
+    pub fn max_message_size(mut self, limit: usize) -> Self {
+        self.max_message_size = Some(limit);
+        self
+    }
+
+    /// Serves the calls and streams sent over a tunnel until its stream ends, then closes the
+    /// sink and returns the result of closing it.
+    ///
+    /// Each tunnel is a single connection, so its calls are counted against a new
+    /// [`connection_limit`](Dispatcher::connection_limit), which is added to `extensions` before
+    /// they are passed to the authorizer. Calls run concurrently, and are answered in the order
+    /// they finish.
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn serve_tunnel<Sx, Rx>(
+        &self,
+        mut extensions: Extensions,
+        sink: &mut Sx,
+        stream: &mut Rx,
+    ) -> Result<(), TransportError<Codec::EncodeError, Codec::DecodeError, Sx::Error>>
+    where
+        Sx: Sink<WebSocketMessage> + Unpin,
+        Rx: Stream<Item = WebSocketMessage> + Unpin,
+    {
+        let Self {
+            dispatcher,
+            codec,
+            max_message_size,
+        } = self;
+        extensions.insert(dispatcher.connection_limit());
+        let extensions = &extensions;
+
+        let mut stream = stream.fuse();
+        let mut calls: FuturesUnordered<BoxFuture<'_, Reply>> = FuturesUnordered::new();
+        let mut streams: SelectAll<BoxStream<'static, (u64, Option<DispatchResult>)>> =
+            SelectAll::new();
+        let mut open: HashMap<u64, AbortHandle> = HashMap::new();
+        let mut next_handle = 0;
+        let mut too_large = None;
+
+        loop {
+            let reply = select! {
+                message = stream.next() => {
+                    let Some(message) = message else {
+                        break;
+                    };
+                    // Messages can't be answered without their ref, so the tunnel is closed
+                    if let Some(limit) =
+                        max_message_size.filter(|limit| message.len() > *limit)
+                    {
+                        too_large = Some(limit);
+                        break;
+                    }
+
+                    match codec.decode::<TunnelMessage<'static, Value>>(&message) {
+                        Ok(TunnelMessage::Request(request)) => {
+                            let msg_ref = request.msg_ref;
+                            calls.push(
+                                dispatcher
+                                    .dispatch_with(request.payload, extensions)
+                                    .map(move |result| match result {
+                                        Ok(data) => TunnelMessage::Response(TunnelResponse {
+                                            msg_ref,
+                                            data,
+                                        }),
+                                        Err(error) => {
+                                            TunnelMessage::Error(TunnelCallError { msg_ref, error })
+                                        }
+                                    })
+                                    .boxed(),
+                            );
+                            None
+                        }
+                        Ok(TunnelMessage::StreamOpen(open_request)) => {
+                            let msg_ref = open_request.msg_ref;
+                            match dispatcher.dispatch_stream_with(open_request.payload, extensions) {
+                                Ok(items) => {
+                                    let handle = next_handle;
+                                    next_handle += 1;
+
+                                    // Streams closed by the client are aborted, which ends them
+                                    // without a close being sent back
+                                    let (items, abort) = stream::abortable(items);
+                                    open.insert(handle, abort);
+                                    streams.push(
+                                        items
+                                            .map(move |item| (handle, Some(item)))
+                                            .chain(stream::once(future::ready((handle, None))))
+                                            .boxed(),
+                                    );
+                                    Some(TunnelMessage::StreamReady(TunnelStreamReady {
+                                        msg_ref,
+                                        handle,
+                                    }))
+                                }
+                                Err(error) => Some(TunnelMessage::StreamOpenError(
+                                    TunnelStreamOpenError { msg_ref, error },
+                                )),
+                            }
+                        }
+                        Ok(
+                            TunnelMessage::StreamClose(TunnelStreamClose { handle })
+                            | TunnelMessage::StreamError(TunnelStreamError { handle, .. }),
+                        ) => {
+                            if let Some(abort) = open.remove(&handle) {
+                                abort.abort();
+                            }
+                            None
+                        }
+                        Ok(TunnelMessage::StreamMessage(TunnelStreamMessage { handle, .. })) => {
+                            // Streams only send items to the client, so the stream is ended
+                            if let Some(abort) = open.remove(&handle) {
+                                abort.abort();
+                            }
+                            Some(TunnelMessage::StreamError(TunnelStreamError {
+                                handle,
+                                error: GenericError::new(
+                                    error_codes::BAD_REQUEST,
+                                    format!("stream {handle} doesn't accept messages"),
+                                ),
+                            }))
+                        }
+                        // The server doesn't make calls, so there is nothing to reply to
+                        Ok(
+                            TunnelMessage::Response(_)
+                            | TunnelMessage::Error(_)
+                            | TunnelMessage::StreamReady(_)
+                            | TunnelMessage::StreamOpenError(_),
+                        ) => None,
+                        Err(_) => match codec.decode::<PartialRefs>(&message) {
+                            Ok(PartialRefs {
+                                msg_ref: Some(msg_ref),
+                            }) => Some(TunnelMessage::Error(TunnelCallError {
+                                msg_ref,
+                                error: GenericError::new(
+                                    error_codes::BAD_REQUEST,
+                                    "failed to decode the message",
+                                ),
+                            })),
+                            _ => None,
+                        },
+                    }
+                }
+                reply = calls.select_next_some() => Some(reply),
+                (handle, item) = streams.select_next_some() => match item {
+                    Some(Ok(data)) => Some(TunnelMessage::StreamMessage(TunnelStreamMessage {
+                        handle,
+                        data,
+                    })),
+                    Some(Err(error)) => {
+                        if let Some(abort) = open.remove(&handle) {
+                            abort.abort();
+                        }
+                        Some(TunnelMessage::StreamError(TunnelStreamError { handle, error }))
+                    }
+                    // Streams that were closed by the client or failed have already been removed
+                    None => open
+                        .remove(&handle)
+                        .map(|_| TunnelMessage::StreamClose(TunnelStreamClose { handle })),
+                },
+            };
+
+            if let Some(reply) = reply {
+                let reply = codec.encode(&reply).map_err(TransportError::EncodeError)?;
+                sink.send(reply).await.map_err(TransportError::SendSink)?;
+            }
+        }
+
+        let closed = sink.close().await.map_err(TransportError::SendSink);
+        match too_large {
+            Some(limit) => Err(TransportError::TooLarge { limit }),
+            None => closed,
+        }
+    }
+}